@@ -0,0 +1,109 @@
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    pbr_types,
+    pbr_functions,
+    view_transformations::position_world_to_clip
+}
+
+#import first_game::wind::{Wind, wind_sway}
+#import first_game::grass_trails::trail_amount
+
+// Keep in sync with the constants of the same name in grass.rs
+const GRASS_HEIGHT: f32 = 2.4;
+const GRASS_STRAIGHTNESS: f32 = 10.0;
+const GRASS_LOD_DISTANCE: f32 = 96.0;
+const GRASS_MAX_LOD_STEP: f32 = 8.0; // 2^GRASS_MAX_LOD_LEVEL
+// Beyond the LOD levels grass shrinks into the ground, ending at the edge of the grass grid
+const GRASS_FADE_START: f32 = 400.0;
+const GRASS_FADE_END: f32 = 512.0;
+
+// Trail map (see grass_trails.rs), when the grass around the player was flattened
+@group(2) @binding(0) var trail_map: texture_2d<f32>;
+const TRAIL_FLATTEN: f32 = 0.8; // how far down a fully trampled blade is pressed
+const TRAIL_LEAN: f32 = 1.2; // how far over it's bent, in radians
+
+// Wind (see wind.rs), updated every frame
+@group(2) @binding(1) var<uniform> wind: Wind;
+
+struct Vertex {
+    // One blade, one unit tall
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+
+    // Per instance, see GrassInstance in grass_instancing.rs
+    @location(8) i_position_height: vec4<f32>,
+    @location(9) i_base_color: vec4<f32>,
+    @location(10) i_tip_color: vec4<f32>,
+    @location(11) i_rotation_shift_lod: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let base = vertex.i_position_height.xyz;
+    let rotation = vertex.i_rotation_shift_lod.x;
+    let lod_step = vertex.i_rotation_shift_lod.z;
+
+    // Density LOD without popping: the step the camera distance calls for changes smoothly, and a blade
+    // shrinks away as that step goes from its own lod_step to twice it. Fewer blades get wider to keep the cover.
+    let view_distance = length(base - view.world_position.xyz);
+    let wanted_step = clamp(exp2(view_distance / GRASS_LOD_DISTANCE), 1.0, GRASS_MAX_LOD_STEP);
+    let lod_scale = 1.0 - smoothstep(lod_step, lod_step * 2.0, wanted_step);
+    let fade = 1.0 - smoothstep(GRASS_FADE_START, GRASS_FADE_END, view_distance);
+    // Trampled grass is pressed down and bent over
+    let trail = trail_amount(trail_map, base.xz, view.world_position.xz);
+    let height = vertex.i_position_height.w * lod_scale * fade * (1.0 - trail * TRAIL_FLATTEN);
+
+    // Scale to the blade's height, then bend it over the same way the baked mesh does
+    var local = vec3<f32>(vertex.position.x * wanted_step, vertex.position.y * height, vertex.position.z);
+    let above_base = local.y;
+    let bend = above_base / (GRASS_HEIGHT * GRASS_STRAIGHTNESS) + trail * TRAIL_LEAN * vertex.position.y;
+    local = vec3<f32>(
+        local.x * cos(bend) - local.y * sin(bend),
+        local.x * sin(bend) + local.y * cos(bend),
+        local.z
+    );
+
+    // Turn to face the blade's rotation
+    let c = cos(rotation);
+    let s = sin(rotation);
+    local = vec3<f32>(local.x * c + local.z * s, local.y, -local.x * s + local.z * c);
+
+    // Wind, as in grass_shader.wgsl
+    let sway = wind_sway(wind, base.xz);
+    local.x += sway.x * (above_base / GRASS_HEIGHT);
+    local.z += sway.y * (above_base / GRASS_HEIGHT);
+
+    out.world_position = vec4<f32>(base + local, 1.0);
+    out.clip_position = position_world_to_clip(out.world_position.xyz);
+    out.world_normal = vertex.normal;
+
+    // Far away the tips blend down to the base colour, close to the ground beneath
+    out.color = mix(vertex.i_base_color, vertex.i_tip_color, fade * vertex.position.y * vertex.i_position_height.w / GRASS_HEIGHT);
+    out.color.r += vertex.i_rotation_shift_lod.y;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Lit like the baked grass' StandardMaterial (see grass_material in grass.rs)
+    var pbr_input = pbr_types::pbr_input_new();
+    pbr_input.material.base_color = in.color;
+    pbr_input.material.perceptual_roughness = 1.0;
+    pbr_input.material.reflectance = 0.5;
+    pbr_input.frag_coord = in.clip_position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = normalize(in.world_normal);
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = pbr_functions::calculate_view(in.world_position, false);
+
+    let color = pbr_functions::apply_pbr_lighting(pbr_input);
+    return pbr_functions::main_pass_post_lighting_processing(pbr_input, color);
+}
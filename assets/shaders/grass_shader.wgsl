@@ -2,7 +2,7 @@
     skinning,
     pbr_fragment::pbr_input_from_standard_material,
     pbr_functions::alpha_discard,
-    mesh_view_bindings::globals,
+    mesh_view_bindings::view,
     mesh_functions,
     morph::morph,
     view_transformations::position_world_to_clip
@@ -15,7 +15,15 @@
 }
 
 
-#import bevy_shader_utils::perlin_noise_2d::perlin_noise_2d
+#import first_game::wind::{Wind, wind_sway}
+#import first_game::grass_trails::trail_amount
+
+// Wind (see wind.rs), kept up to date on the shared grass material
+@group(2) @binding(102) var<uniform> wind: Wind;
+
+// Trail map (see grass_trails.rs), when the grass around the player was flattened
+@group(2) @binding(100) var trail_map: texture_2d<f32>;
+const TRAIL_FLATTEN: f32 = 0.8; // how far down a fully trampled blade is pressed
 
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
@@ -54,11 +62,16 @@ fn vertex(vertex_no_morph: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
     // calculation of wind and new x, y, z coords
-    var noise = perlin_noise_2d(vec2<f32>(vertex_no_morph.world_position.x/50.0 + globals.time * 0.5, vertex_no_morph.world_position.z/50.0 + globals.time * 0.5));
+    let sway = wind_sway(wind, vertex_no_morph.world_position.xz);
+
+    // trampled grass is pressed down and over
+    let trail = trail_amount(trail_map, vertex_no_morph.world_position.xz, view.world_position.xz);
+    let above_base = (vertex_no_morph.position.y - vertex_no_morph.base_y) * (1.0 - trail * TRAIL_FLATTEN);
+    let lean = (vertex_no_morph.position.y - vertex_no_morph.base_y) * trail * TRAIL_FLATTEN;
 
-    var new_x = vertex_no_morph.starting_position.x + noise * ((vertex_no_morph.position.y-vertex_no_morph.base_y) / 2.4);
-    var new_y = vertex_no_morph.position.y;
-    var new_z = vertex_no_morph.starting_position.z + noise * ((vertex_no_morph.position.y-vertex_no_morph.base_y) / 2.4);
+    var new_x = vertex_no_morph.starting_position.x + sway.x * (above_base / 2.4) + lean;
+    var new_y = vertex_no_morph.base_y + above_base;
+    var new_z = vertex_no_morph.starting_position.z + sway.y * (above_base / 2.4);
 
 #ifdef MORPH_TARGETS
     var vertex = morph_vertex(vertex_no_morph);
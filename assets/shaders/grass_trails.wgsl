@@ -0,0 +1,29 @@
+#define_import_path first_game::grass_trails
+
+#import bevy_pbr::mesh_view_bindings::globals
+
+// Keep in sync with the constants of the same name in grass_trails.rs
+const TRAIL_MAP_SIZE: i32 = 256;
+const TRAIL_TEXEL_SIZE: f32 = 0.5;
+const TRAIL_RECOVERY_TIME: f32 = 20.0;
+// How far from the camera the trail map reaches, beyond it the map holds another spot's trails
+const TRAIL_MAP_REACH: f32 = 57.6; // 0.45 * TRAIL_MAP_SIZE * TRAIL_TEXEL_SIZE
+
+// How flattened one texel is now, from when it was flattened (in globals.time seconds)
+fn texel_trail(trail_map: texture_2d<f32>, texel: vec2<i32>) -> f32 {
+    let flattened_at = textureLoad(trail_map, texel & vec2<i32>(TRAIL_MAP_SIZE - 1), 0).r;
+    return min(exp((flattened_at - globals.time) / TRAIL_RECOVERY_TIME), 1.0);
+}
+
+// How flattened the grass is at a world position (0..1), blended between the four nearest texels
+fn trail_amount(trail_map: texture_2d<f32>, world_xz: vec2<f32>, camera_xz: vec2<f32>) -> f32 {
+    if distance(world_xz, camera_xz) > TRAIL_MAP_REACH {
+        return 0.0;
+    }
+    let texel = world_xz / TRAIL_TEXEL_SIZE - 0.5;
+    let corner = vec2<i32>(floor(texel));
+    let t = fract(texel);
+    let near = mix(texel_trail(trail_map, corner), texel_trail(trail_map, corner + vec2<i32>(1, 0)), t.x);
+    let far = mix(texel_trail(trail_map, corner + vec2<i32>(0, 1)), texel_trail(trail_map, corner + vec2<i32>(1, 1)), t.x);
+    return mix(near, far, t.y);
+}
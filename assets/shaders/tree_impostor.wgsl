@@ -0,0 +1,111 @@
+#import bevy_pbr::{
+    mesh_functions,
+    mesh_view_bindings::view,
+    pbr_types,
+    pbr_functions,
+    view_transformations::position_world_to_clip
+}
+
+// Atlas layout, keep in sync with tree_impostor.rs
+const IMPOSTOR_FRAMES: f32 = 8.0;
+const SPECIES_COUNT: f32 = 4.0; // TreeSpecies::ALL
+// Cross-fade from the detailed trees, keep in sync with tree_shader.wgsl
+const IMPOSTOR_FADE_START: f32 = 120.0;
+const IMPOSTOR_FADE_END: f32 = 160.0;
+
+@group(2) @binding(0) var albedo_texture: texture_2d<f32>;
+@group(2) @binding(1) var albedo_sampler: sampler;
+@group(2) @binding(2) var normal_texture: texture_2d<f32>;
+@group(2) @binding(3) var normal_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    // Middle of the tree's bounds, the same for all four corners
+    @location(0) center: vec3<f32>,
+    // Corner of the quad, 0,0 at the top left
+    @location(1) corner: vec2<f32>,
+    // Radius of the bounds and TreeSpecies::index
+    @location(2) radius_species: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+// Direction on the upper hemisphere to 0..1 on both axes
+fn hemi_octahedral_encode(d: vec3<f32>) -> vec2<f32> {
+    let p = d.xz / (abs(d.x) + abs(d.y) + abs(d.z));
+    return vec2<f32>(p.x + p.y, p.x - p.y) * 0.5 + 0.5;
+}
+
+// Same as hemi_octahedral_decode in tree_impostor.rs
+fn hemi_octahedral_decode(uv: vec2<f32>) -> vec3<f32> {
+    let e = uv * 2.0 - 1.0;
+    let p = vec2<f32>(e.x + e.y, e.x - e.y) * 0.5;
+    return normalize(vec3<f32>(p.x, 1.0 - abs(p.x) - abs(p.y), p.y));
+}
+
+// Interleaved gradient noise, the same pattern as in tree_shader.wgsl
+fn dither(frag_coord: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(frag_coord, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let model = mesh_functions::get_model_matrix(vertex.instance_index);
+    let center = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(vertex.center, 1.0)).xyz;
+    let radius = vertex.radius_species.x;
+    let species = vertex.radius_species.y;
+
+    // Pick the baked view closest to where the camera is, never from below the horizon
+    var to_camera = view.world_position.xyz - center;
+    to_camera.y = max(to_camera.y, 0.0);
+    let direction = normalize(to_camera + vec3<f32>(0.0, 0.0001, 0.0));
+    let frame = clamp(round(hemi_octahedral_encode(direction) * (IMPOSTOR_FRAMES - 1.0)), vec2<f32>(0.0), vec2<f32>(IMPOSTOR_FRAMES - 1.0));
+
+    // Face the quad the way that view was baked, same as impostor_basis in tree_impostor.rs
+    let frame_direction = hemi_octahedral_decode(frame / (IMPOSTOR_FRAMES - 1.0));
+    var right = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(frame_direction.y) <= 0.999 {
+        right = normalize(cross(vec3<f32>(0.0, 1.0, 0.0), frame_direction));
+    }
+    let up = cross(frame_direction, right);
+    let offset = (vertex.corner.x * 2.0 - 1.0) * right + (1.0 - vertex.corner.y * 2.0) * up;
+
+    out.world_position = vec4<f32>(center + offset * radius, 1.0);
+    out.clip_position = position_world_to_clip(out.world_position.xyz);
+    out.uv = (vec2<f32>(species * IMPOSTOR_FRAMES + frame.x, frame.y) + vertex.corner)
+        / vec2<f32>(IMPOSTOR_FRAMES * SPECIES_COUNT, IMPOSTOR_FRAMES);
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let albedo = textureSample(albedo_texture, albedo_sampler, in.uv);
+    let normal = textureSample(normal_texture, normal_sampler, in.uv).xyz * 2.0 - 1.0;
+    if albedo.a < 0.5 {
+        discard;
+    }
+    // Only the pixels the detailed tree dropped, so the two never overlap and never leave a gap
+    let fade = smoothstep(IMPOSTOR_FADE_START, IMPOSTOR_FADE_END, distance(in.world_position.xz, view.world_position.xz));
+    if fade <= dither(in.clip_position.xy) {
+        discard;
+    }
+
+    // Lit like the detailed trees' StandardMaterial (see tree_material in tree.rs)
+    var pbr_input = pbr_types::pbr_input_new();
+    pbr_input.material.base_color = vec4<f32>(albedo.rgb, 1.0);
+    pbr_input.material.perceptual_roughness = 1.0;
+    pbr_input.material.reflectance = 0.3;
+    pbr_input.frag_coord = in.clip_position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = normalize(normal);
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = pbr_functions::calculate_view(in.world_position, false);
+
+    let color = pbr_functions::apply_pbr_lighting(pbr_input);
+    return pbr_functions::main_pass_post_lighting_processing(pbr_input, color);
+}
@@ -2,8 +2,8 @@
     skinning,
     pbr_fragment::pbr_input_from_standard_material,
     pbr_functions::alpha_discard,
-    mesh_view_bindings::globals,
     mesh_functions,
+    mesh_view_bindings::view,
     morph::morph,
     view_transformations::position_world_to_clip
 }
@@ -15,7 +15,10 @@
 }
 
 
-#import bevy_shader_utils::perlin_noise_2d::perlin_noise_2d
+#import first_game::wind::{Wind, wind_sway}
+
+// Wind (see wind.rs), kept up to date on the shared tree material
+@group(2) @binding(100) var<uniform> wind: Wind;
 
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
@@ -54,11 +57,11 @@ fn vertex(vertex_no_morph: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
     // calculation of wind and new x, y, z coords
-    var noise = perlin_noise_2d(vec2<f32>(vertex_no_morph.world_position.x/50.0 + globals.time * 0.5, vertex_no_morph.world_position.z/50.0 + globals.time * 0.5));
+    let sway = wind_sway(wind, vertex_no_morph.world_position.xz);
 
-    var new_x = vertex_no_morph.starting_position.x + noise * ((vertex_no_morph.position.y-vertex_no_morph.base_y) / 6.0);
+    var new_x = vertex_no_morph.starting_position.x + sway.x * ((vertex_no_morph.position.y-vertex_no_morph.base_y) / 6.0);
     var new_y = vertex_no_morph.position.y;
-    var new_z = vertex_no_morph.starting_position.z + noise * ((vertex_no_morph.position.y-vertex_no_morph.base_y) / 6.0);
+    var new_z = vertex_no_morph.starting_position.z + sway.y * ((vertex_no_morph.position.y-vertex_no_morph.base_y) / 6.0);
 
 #ifdef MORPH_TARGETS
     var vertex = morph_vertex(vertex_no_morph);
@@ -122,3 +125,29 @@ fn vertex(vertex_no_morph: Vertex) -> VertexOutput {
 
     return out;
 }
+
+// Cross-fade into the impostors (see tree_impostor.wgsl), keep in sync with the constants there
+const IMPOSTOR_FADE_START: f32 = 120.0;
+const IMPOSTOR_FADE_END: f32 = 160.0;
+
+// Interleaved gradient noise, the same pattern as in tree_impostor.wgsl
+fn dither(frag_coord: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(frag_coord, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    // Drop the pixels the impostor draws, so the two never overlap and never leave a gap
+    let fade = smoothstep(IMPOSTOR_FADE_START, IMPOSTOR_FADE_END, distance(in.world_position.xz, view.world_position.xz));
+    if fade > dither(in.position.xy) {
+        discard;
+    }
+
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,34 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+    mesh_view_bindings::globals,
+}
+
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+
+// Keep in sync with WATER_TEXTURE_SCALE in terrain.rs
+const WATER_TEXTURE_SCALE: f32 = 20.0;
+// Texture repeats per second. globals.time wraps every hour, in which this scrolls a whole
+// number of repeats (45), so the wrap doesn't show
+const WATER_SCROLL_SPEED: f32 = 0.0125;
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    // UVs come from the world position, so every water surface lines up and the ocean
+    // snapping along with the player doesn't make the texture jump
+    var scrolled = in;
+#ifdef VERTEX_UVS
+    scrolled.uv = in.world_position.xz / WATER_TEXTURE_SCALE + globals.time * WATER_SCROLL_SPEED;
+#endif
+
+    var pbr_input = pbr_input_from_standard_material(scrolled, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,21 @@
+#define_import_path first_game::wind
+
+#import bevy_shader_utils::perlin_noise_2d::perlin_noise_2d
+
+// Keep in sync with WindUniform in wind.rs
+struct Wind {
+    direction: vec2<f32>,
+    speed: f32,
+    turbulence_scale: f32,
+    scroll: vec2<f32>,
+};
+
+// Keep in sync with SWAY_BIAS in wind.rs
+const SWAY_BIAS: f32 = 0.3;
+
+// How far the top of something one unit tall is pushed on the xz plane.
+// Wind::sway on the CPU uses another noise function, but the same gusts, scale and drift.
+fn wind_sway(wind: Wind, world_xz: vec2<f32>) -> vec2<f32> {
+    let turbulence = perlin_noise_2d(world_xz / wind.turbulence_scale + wind.scroll);
+    return wind.direction * wind.speed * (SWAY_BIAS + turbulence);
+}
@@ -1,20 +1,23 @@
-use crate::entities::terrain;
-use crate::{entities, util::perlin::sample_terrain_height};
-use bevy::ecs::system::{CommandQueue, SystemState};
+use std::f32::consts::TAU;
 use bevy::math::Vec3A;
 use bevy::pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline};
 use bevy::render::mesh::{MeshVertexAttribute, MeshVertexBufferLayout};
 use bevy::render::primitives::Aabb;
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy::render::render_resource::{AsBindGroup, RenderPipelineDescriptor, SpecializedMeshPipelineError, VertexFormat};
-use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
-use bevy::{prelude::*, render::{render_resource::PrimitiveTopology, mesh::{self}}, utils::HashMap};
+use bevy::{prelude::*, render::{render_resource::PrimitiveTopology, mesh::{self}}};
 use noise::NoiseFn;
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use crate::util::perlin::{self};
-use crate::util::render_state::RenderState;
-use futures_lite::future::poll_once;
-use super::player::ContainsPlayer;
+use crate::util::biome::{self, Climate};
+use crate::util::heightfield::{HeightSampler, TerrainSampler};
+use crate::util::streaming::{tile_distance, tile_seed, LayerGenerator, StreamingPlugin, StreamingSettings, TileKey, TileRequest};
+use crate::util::wind::{update_wind, Wind, WindUniform};
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
+use super::grass_instancing::{GrassInstance, GrassInstances, GrassInstancingPlugin};
+use super::grass_trails::{GrassTrailMap, GrassTrailsPlugin};
 
 // Grass constants
 const GRASS_TILE_SIZE: f32 = 32.;
@@ -26,13 +29,22 @@ pub const GRASS_SECOND_COLOR: [f32;4] = [0.079,0.079,0.,1.];
 const GRASS_HEIGHT_VARIATION_FACTOR: f32 = 0.2;
 const GRASS_STRAIGHTNESS: f32 = 10.0; // for now, as opposed to a curve factor, just modifying denominator for curve calcs
 const GRASS_OFFSET: f32 = 0.2;
-const DESPAWN_DISTANCE: f32 = (GRID_SIZE_HALF+1) as f32 * GRASS_TILE_SIZE + GRID_SIZE_HALF as f32;
 
 const ATTRIBUTE_BASE_Y: MeshVertexAttribute = MeshVertexAttribute::new("BaseY", 988540917, VertexFormat::Float32);
 const ATTRIBUTE_STARTING_POSITION: MeshVertexAttribute = MeshVertexAttribute::new("StartingPosition", 988540916, VertexFormat::Float32x3);
 const ATTRIBUTE_WORLD_POSITION: MeshVertexAttribute = MeshVertexAttribute::new("WorldPosition", 988540915, VertexFormat::Float32x3);
 
-const GRID_SIZE_HALF: i32 = 8;
+const GRID_SIZE_HALF: i32 = 16; // grass_instanced.wgsl fades grass out by GRASS_FADE_END, the edge of the grid
+const MAX_TILES_PER_FRAME: usize = 16; // grass tiles started per frame
+// LOD: blade density halves in each direction every GRASS_LOD_DISTANCE from the camera, down to 1 in 2^GRASS_MAX_LOD_LEVEL.
+// Keep in sync with grass_instanced.wgsl, which fades blades out smoothly between levels
+const GRASS_LOD_DISTANCE: f32 = 96.0;
+const GRASS_MAX_LOD_LEVEL: u32 = 3;
+// Draw grass as instances of one blade (see grass_instancing.rs) rather than baking every blade into a mesh per tile
+const GRASS_INSTANCING: bool = true;
+// Climate and slope change slowly, so they're sampled every this many blades along a row and interpolated in between.
+// Only the ground height is sampled for every blade.
+const COVER_SAMPLE_BLADES: u32 = 8;
 
 fn grass_material() -> StandardMaterial {
     StandardMaterial {
@@ -55,51 +67,146 @@ pub struct GrassData {
 #[derive(Component,Clone)]
 pub struct Grass;
 
-// Grass offsets component
+/// One blade of grass, as placed on a tile. Both the baked mesh and the instanced path draw from these.
+#[derive(Clone, Copy, Debug)]
+pub struct GrassBlade {
+    /// Offset from the tile center on x and z, world height of the blade's base on y
+    pub position: Vec3,
+    pub height: f32,
+    pub base_color: [f32; 4],
+    pub tip_color: [f32; 4],
+    /// Added to the red channel so patches of grass vary a little
+    pub color_shift: f32,
+    /// Facing around y, in radians
+    pub rotation: f32,
+    /// Coarsest LOD step (blades kept per row shrinks by this factor) the blade is still part of
+    pub lod_step: u32,
+}
+
+/// LOD step for a grass tile: 1 keeps every blade, 2 every other blade in each direction, and so on.
+/// Chosen from the closest the camera can be to the tile, so the shader never wants a blade that was left out.
+fn get_lod_step(tile_distance: i32) -> u32 {
+    // A tile of slack for the camera's offset from the player
+    let min_distance = (tile_distance - 2).max(0) as f32 * GRASS_TILE_SIZE;
+    1 << ((min_distance / GRASS_LOD_DISTANCE) as u32).min(GRASS_MAX_LOD_LEVEL)
+}
+
+/// Blade (i, j) of a tile on the world's grass lattice, which runs on across tile borders
+fn blade_lattice_point(key: TileKey, i: u32, j: u32) -> (i32, i32) {
+    (key.0 * BLADES_PER_ROW as i32 + i as i32, key.1 * BLADES_PER_ROW as i32 + j as i32)
+}
+
+/// Climate and slope over one tile on a coarse grid, lined up with the blade lattice so neighbouring tiles agree at their border
+struct CoverGrid {
+    /// (climate, slope) per grid point, row by row along x
+    samples: Vec<(Climate, f32)>,
+    row_size: usize,
+    cell_size: f32,
+}
+
+impl CoverGrid {
+    fn new(sampler: &dyn HeightSampler, center_x: f32, center_z: f32) -> Self {
+        let cells = (BLADES_PER_ROW / COVER_SAMPLE_BLADES) as usize;
+        let cell_size = GRASS_TILE_SIZE / cells as f32;
+        let mut samples = Vec::with_capacity((cells + 1) * (cells + 1));
+        for j in 0..=cells {
+            let z = center_z - GRASS_TILE_SIZE / 2.0 + j as f32 * cell_size;
+            for i in 0..=cells {
+                let x = center_x - GRASS_TILE_SIZE / 2.0 + i as f32 * cell_size;
+                samples.push((sampler.climate(x, z), sampler.slope(x, z)));
+            }
+        }
+        Self { samples, row_size: cells + 1, cell_size }
+    }
+
+    /// Bilinear climate and slope at an offset from the tile center
+    fn sample(&self, x: f32, z: f32) -> (Climate, f32) {
+        let last = (self.row_size - 1) as f32;
+        let gx = ((x + GRASS_TILE_SIZE / 2.0) / self.cell_size).clamp(0.0, last);
+        let gz = ((z + GRASS_TILE_SIZE / 2.0) / self.cell_size).clamp(0.0, last);
+        let (ix, iz) = ((gx as usize).min(self.row_size - 2), (gz as usize).min(self.row_size - 2));
+        let (fx, fz) = (gx - ix as f32, gz - iz as f32);
+        let corner = |i: usize, j: usize| self.samples[(iz + j) * self.row_size + ix + i];
+        let weights = [(1.0 - fx) * (1.0 - fz), fx * (1.0 - fz), (1.0 - fx) * fz, fx * fz];
+        let mut climate = Climate::default();
+        let mut slope = 0.0;
+        for (weight, (i, j)) in weights.into_iter().zip([(0, 0), (1, 0), (0, 1), (1, 1)]) {
+            let (corner_climate, corner_slope) = corner(i, j);
+            climate.temperature += corner_climate.temperature * weight;
+            climate.moisture += corner_climate.moisture * weight;
+            slope += corner_slope * weight;
+        }
+        (climate, slope)
+    }
+}
+
+/// Scatter blades over the tile's BLADES_PER_ROW lattice, keeping every step-th row and column, thinned out by biome::grass_cover.
+/// Each blade is randomised from its own lattice point, so a blade looks the same at every LOD step it survives to
+/// and coarser tiles are an exact subset of finer ones.
+pub fn place_grass_blades(config: &WorldGenConfig, sampler: &dyn HeightSampler, key: TileKey, step: u32) -> Vec<GrassBlade> {
+    let mut blades = vec![];
+    let height_perlin = perlin::grass_perlin(config);
+    let terrain_perlin = perlin::terrain_perlin(config);
+    let (spawn_x, spawn_z) = GrassLayer::settings().tile_center(key);
+    let spacing = GRASS_TILE_SIZE / BLADES_PER_ROW as f32;
+    let start_x = - GRASS_TILE_SIZE/2.;
+    let start_z = - GRASS_TILE_SIZE/2.;
+    let cover_grid = CoverGrid::new(sampler, spawn_x, spawn_z);
+    for i in (0..BLADES_PER_ROW).step_by(step as usize) {
+        let x = start_x + i as f32 * spacing;
+        for j in (0..BLADES_PER_ROW).step_by(step as usize) {
+            let z = start_z + j as f32 * spacing;
+            let mut rng = StdRng::seed_from_u64(tile_seed(config.terrain_seed, blade_lattice_point(key, i, j)));
+            let rand1 = if GRASS_OFFSET!=0.0 {rng.gen_range(-GRASS_OFFSET..GRASS_OFFSET)} else {0.0};
+            let rand2 = if GRASS_OFFSET!=0.0 {rng.gen_range(-GRASS_OFFSET..GRASS_OFFSET)} else {0.0};
+            let x_offset = x + rand1;
+            let z_offset = z + rand2;
+            let world_x = spawn_x + x_offset;
+            let world_z = spawn_z + z_offset;
+            let terrain_y = sampler.height(world_x, world_z);
+            let y = terrain_y - 0.2; // minus small amount to avoid floating
+            let (climate, slope) = cover_grid.sample(x_offset, z_offset);
+            let cover = biome::grass_cover(config, climate, terrain_y, slope);
+            let keep = rng.gen::<f32>() < cover.density;
+            if keep {
+                blades.push(GrassBlade {
+                    position: Vec3::new(x_offset, y, z_offset),
+                    height: cover.height * (GRASS_HEIGHT + (height_perlin.get([world_x as f64, world_z as f64]) as f32 * GRASS_HEIGHT_VARIATION_FACTOR)),
+                    base_color: cover.base_color,
+                    tip_color: cover.tip_color,
+                    color_shift: (terrain_perlin.get([world_x as f64 / 100., world_z as f64 / 100.]) * 0.01) as f32,
+                    rotation: rng.gen_range(0.0..TAU),
+                    lod_step: 1 << (i | j).trailing_zeros().min(GRASS_MAX_LOD_LEVEL),
+                });
+            }
+        }
+    }
+    blades
+}
 
-pub fn generate_grass_mesh(
-    spawn_x: f32,
-    spawn_z: f32,
-    density: u32,
-    tile_size: f32,
-) -> (Mesh, GrassData) {
+/// Bake a tile's blades into one mesh, with per-vertex attributes for the wind shader
+pub fn generate_grass_mesh(config: &WorldGenConfig, key: TileKey, blades: &[GrassBlade]) -> (Mesh, GrassData) {
     let mut grass_offsets = vec![];
-    let mut rng = thread_rng();
+    let (spawn_x, spawn_z) = GrassLayer::settings().tile_center(key);
+    // Seeded per tile so a tile looks the same every time it's streamed back in
+    let mut rng = StdRng::seed_from_u64(tile_seed(config.terrain_seed, key));
     let asset_usage = RenderAssetUsages::RENDER_WORLD;// | RenderAssetUsages::MAIN_WORLD;
     // let asset_usage = RenderAssetUsages::RENDER_WORLD;
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, asset_usage);
     let mut all_verts: Vec<Vec3> = vec![];
     let mut all_indices: Vec<u32> = vec![];
     let mut all_colors: Vec<[f32; 4]> = vec![];
-    let mut blade_number = 0;
-    let height_perlin = perlin::grass_perlin();
-    let terrain_perlin = perlin::terrain_perlin();
-    let start_x = - tile_size/2.;
-    let start_z = - tile_size/2.;
-    for i in 0..density {
-        let x = start_x + i as f32 * tile_size / density as f32;
-        for j in 0..density {
-            let z = start_z + j as f32 * tile_size / density as f32;
-            let rand1 = if GRASS_OFFSET!=0.0 {rng.gen_range(-GRASS_OFFSET..GRASS_OFFSET)} else {0.0};
-            let rand2 = if GRASS_OFFSET!=0.0 {rng.gen_range(-GRASS_OFFSET..GRASS_OFFSET)} else {0.0};
-            let x_offset = x + rand1;
-            let z_offset = z + rand2;
-            let y = sample_terrain_height(&terrain_perlin, spawn_x + x_offset, spawn_z + z_offset) - 0.2; // minus small amount to avoid floating
-            let blade_height = GRASS_HEIGHT + (height_perlin.get([(spawn_x + x_offset) as f64, (spawn_z + z_offset) as f64]) as f32 * GRASS_HEIGHT_VARIATION_FACTOR);
-            if y > terrain::HEIGHT_TEMPERATE_START && y < terrain::HEIGHT_TEMPERATE_END {
-                let (mut verts, mut indices) = generate_single_blade_verts(x_offset, y, z_offset, blade_number, blade_height);
-                for v in &verts {
-                    grass_offsets.push([spawn_x + x_offset,y,spawn_z + z_offset]);
-                    let r_color_shift = (terrain_perlin.get([(spawn_x + x_offset) as f64 / 100., (spawn_z + z_offset) as f64 / 100.]) * 0.01) as f32;
-                    let mut color = color_gradient_y_based(v.y-y, GRASS_BASE_COLOR_2, GRASS_SECOND_COLOR);
-                    color[0] += r_color_shift;
-                    all_colors.push(color);
-                }
-                all_verts.append(&mut verts);
-                all_indices.append(&mut indices);
-                blade_number += 1;
-            }
+    for (blade_number, blade) in blades.iter().enumerate() {
+        let Vec3 { x, y, z } = blade.position;
+        let (mut verts, mut indices) = generate_single_blade_verts(&mut rng, x, y, z, blade_number as u32, blade.height);
+        for v in &verts {
+            grass_offsets.push([spawn_x + x, y, spawn_z + z]);
+            let mut color = color_gradient_y_based(v.y-y, blade.base_color, blade.tip_color);
+            color[0] += blade.color_shift;
+            all_colors.push(color);
         }
+        all_verts.append(&mut verts);
+        all_indices.append(&mut indices);
     }
 
     generate_grass_geometry(&all_verts, all_indices, &mut mesh, &grass_offsets, all_colors);
@@ -111,42 +218,7 @@ pub fn generate_grass_mesh(
     )
 }
 
-pub fn generate_grass(
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<ExtendedMaterial<StandardMaterial,GrassMaterialExtension>>>,
-    spawn_x: f32,
-    spawn_z: f32,
-    density: u32,
-    tile_size: f32,
-) -> (MaterialMeshBundle<ExtendedMaterial<StandardMaterial, GrassMaterialExtension>>, Grass, GrassData) {
-    let (mesh, grass_data) = generate_grass_mesh(spawn_x, spawn_z, density, tile_size);
-
-    let grass_material_ext = GrassMaterialExtension {
-    };
-    
-    let grass_material_std = grass_material();
-
-    let grass_material = ExtendedMaterial {
-        base: grass_material_std,
-        extension: grass_material_ext
-    };
-
-    let bundle = MaterialMeshBundle {
-        mesh: meshes.add(mesh),
-        material: materials.add(grass_material),
-        transform: Transform::from_xyz(spawn_x,0.,spawn_z),
-        ..default()
-    };
-
-    (
-        bundle,
-        Grass {},
-        grass_data,
-    )
-
-}
-
-pub fn generate_single_blade_verts(x: f32, y: f32, z: f32, blade_number: u32, blade_height: f32) -> (Vec<Vec3>, Vec<u32>) {
+pub fn generate_single_blade_verts(rng: &mut impl Rng, x: f32, y: f32, z: f32, blade_number: u32, blade_height: f32) -> (Vec<Vec3>, Vec<u32>) {
     // For grass with 7 vertices, uncomment t3-6, and uncomment indices
     // vertex transforms
     let t1 = Transform::from_xyz(x, y, z);
@@ -164,13 +236,13 @@ pub fn generate_single_blade_verts(x: f32, y: f32, z: f32, blade_number: u32, bl
     
     // // physical randomization of grass blades
     // rotate grass randomly around y
-    apply_y_rotation(&mut transforms, x, y, z);
+    apply_y_rotation(rng, &mut transforms, x, y, z);
     
     // curve the grass all one way
-    apply_curve(&mut transforms, x, y, z);
+    apply_curve(rng, &mut transforms, x, y, z);
 
     // rotate grass again
-    apply_y_rotation(&mut transforms, x, y, z);
+    apply_y_rotation(rng, &mut transforms, x, y, z);
     
     let verts: Vec<Vec3> = transforms.iter().map(|t| t.translation).collect();
 
@@ -184,9 +256,9 @@ pub fn generate_single_blade_verts(x: f32, y: f32, z: f32, blade_number: u32, bl
     (verts, indices)
 }
 
-fn apply_y_rotation(transforms: &mut Vec<Transform>, x: f32, y:f32, z: f32) {
+fn apply_y_rotation(rng: &mut impl Rng, transforms: &mut Vec<Transform>, x: f32, y:f32, z: f32) {
     let y_rotation_point = Vec3::new(x,y,z);
-    let rand_rotation = (thread_rng().gen_range(0..628) / 100) as f32;
+    let rand_rotation = (rng.gen_range(0..628) / 100) as f32;
     for t in transforms {
         t.rotate_around(y_rotation_point, Quat::from_rotation_y(rand_rotation));
     }
@@ -195,9 +267,9 @@ fn apply_y_rotation(transforms: &mut Vec<Transform>, x: f32, y:f32, z: f32) {
 }
 
 // todo: clean up
-fn apply_curve(transforms: &mut Vec<Transform>, x: f32, y:f32, z: f32) {
-    let curve_rotation_point = Vec3::new(x + thread_rng().gen_range(0..2) as f32 / 10.0, y, z + thread_rng().gen_range(0..2) as f32 / 10.0);
-    let rand_curve = (thread_rng().gen_range(101..110) / 100) as f32;
+fn apply_curve(rng: &mut impl Rng, transforms: &mut Vec<Transform>, x: f32, y:f32, z: f32) {
+    let curve_rotation_point = Vec3::new(x + rng.gen_range(0..2) as f32 / 10.0, y, z + rng.gen_range(0..2) as f32 / 10.0);
+    let rand_curve = (rng.gen_range(101..110) / 100) as f32;
     for t in transforms {
         t.rotate_around(curve_rotation_point, Quat::from_rotation_z(rand_curve * ((t.translation.y - y) / (GRASS_HEIGHT*GRASS_STRAIGHTNESS))));
     }
@@ -230,32 +302,16 @@ pub fn generate_grass_geometry(verts: &Vec<Vec3>, vec_indices: Vec<u32>, mesh: &
 
 }
 
-/// Grass-specific tile state that wraps the unified RenderState
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub struct GrassTileState {
-    pub render_state: RenderState,
-}
-
-impl GrassTileState {
-    pub fn visible() -> Self {
-        Self { render_state: RenderState::Visible }
-    }
-
-    pub fn pending() -> Self {
-        Self { render_state: RenderState::Pending }
-    }
-}
-
-#[derive(Component)]
-struct GrassGrid(HashMap<(i32, i32), GrassTileState>);
-
 /// Create an Aabb for a grass tile (centered at local origin)
-/// Grass mesh vertices are at actual terrain heights (not relative to transform)
-fn grass_tile_aabb() -> Aabb {
+/// Grass mesh vertices are at actual terrain heights (not relative to transform),
+/// so the Aabb spans from the lowest blade's base to the tallest blade's tip
+fn grass_tile_aabb(blades: &[GrassBlade]) -> Aabb {
     let half_size = GRASS_TILE_SIZE / 2.0;
-    // Grass exists in temperate zone (210-800) plus grass height above terrain
-    let min_height = terrain::HEIGHT_TEMPERATE_START - 10.0;
-    let max_height = terrain::HEIGHT_TEMPERATE_END + GRASS_HEIGHT * 1.5;
+    if blades.is_empty() {
+        return Aabb::default();
+    }
+    let min_height = blades.iter().map(|blade| blade.position.y).fold(f32::MAX, f32::min);
+    let max_height = blades.iter().map(|blade| blade.position.y + blade.height * 1.5).fold(f32::MIN, f32::max);
     let center_y = (min_height + max_height) / 2.0;
     let half_height = (max_height - min_height) / 2.0;
     Aabb {
@@ -264,155 +320,144 @@ fn grass_tile_aabb() -> Aabb {
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn update_grass(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial,GrassMaterialExtension>>>,
-    mut grass: Query<(Entity, &Handle<Mesh>, &GrassData, &Transform, &ViewVisibility, &mut ContainsPlayer), With<Grass>>,
-    mut grid: Query<&mut GrassGrid>,
-    time: Res<Time>,
-    player: Query<(Entity,&Transform),With<entities::player::Player>>,
-) {
-    let (_, player_trans) = player.get_single().unwrap();
-    let x = player_trans.translation.x;
-    let z = player_trans.translation.z;
-
-    if grass.is_empty() && grid.is_empty() {
-        let mut grass_grid = GrassGrid(HashMap::new());
-        let aabb = grass_tile_aabb();
-        // generate grid of grass
-        for i in -GRID_SIZE_HALF..=GRID_SIZE_HALF {
-            for j in -GRID_SIZE_HALF..=GRID_SIZE_HALF {
-                let a = x + i as f32 * GRASS_TILE_SIZE;
-                let b = z + j as f32 * GRASS_TILE_SIZE;
-
-                grass_grid.0.insert((a as i32, b as i32), GrassTileState::visible());
-                let contains_player = (player_trans.translation.x - a).abs() < GRASS_TILE_SIZE/2. && (player_trans.translation.z - b).abs() < GRASS_TILE_SIZE/2.;
-                let (main_mat, main_grass, main_data) = generate_grass(&mut meshes, &mut materials, a, b, BLADES_PER_ROW, GRASS_TILE_SIZE);
-                commands.spawn(main_mat)
-                    .insert(main_grass)
-                    .insert(main_data)
-                    .insert(ContainsPlayer(contains_player))
-                    .insert(aabb);
-            }
+/// Streams grass tiles around the player (see util::streaming)
+pub struct GrassLayer {
+    config: WorldGenConfig,
+    sampler: TerrainSampler,
+}
+
+/// A generated grass tile and the bounds of its blades
+pub struct GrassTile {
+    data: GrassTileData,
+    aabb: Aabb,
+}
+
+/// A generated grass tile's blades, in whichever form GRASS_INSTANCING picks
+pub enum GrassTileData {
+    /// Every blade baked into one mesh
+    Baked(Mesh, GrassData),
+    /// One instance per blade, drawn with the shared blade mesh
+    Instanced(Vec<GrassInstance>),
+}
+
+impl LayerGenerator for GrassLayer {
+    /// LOD step, see get_lod_step
+    type Lod = u32;
+    type Output = GrassTile;
+
+    fn settings() -> StreamingSettings {
+        StreamingSettings {
+            tile_size: GRASS_TILE_SIZE,
+            radius: GRID_SIZE_HALF,
+            max_spawns_per_frame: MAX_TILES_PER_FRAME,
         }
-        commands.spawn(grass_grid);
-    } else {
-        let thread_pool = AsyncComputeTaskPool::get();
-        let mut grass_grid = grid.get_single_mut().unwrap();
-        let _elapsed_time = time.elapsed_seconds_f64();
-        let mut grass_w_player: Option<Entity> = None;
-
-        // Update grid state for completed async tasks (Pending -> Visible)
-        for (_, _, _, grass_trans, _, _) in grass.iter() {
-            let tile_key = (grass_trans.translation.x as i32, grass_trans.translation.z as i32);
-            if let Some(state) = grass_grid.0.get(&tile_key) {
-                if state.render_state == RenderState::Pending {
-                    grass_grid.0.insert(tile_key, GrassTileState::visible());
+    }
+
+    fn new(config: &WorldGenConfig, sampler: &TerrainSampler, _deltas: &WorldDeltas) -> Self {
+        Self { config: config.clone(), sampler: sampler.clone() }
+    }
+
+    fn lod(key: TileKey, player_key: TileKey) -> u32 {
+        get_lod_step(tile_distance(key, player_key))
+    }
+
+    fn generate(&self, request: &TileRequest<u32>) -> GrassTile {
+        let blades = place_grass_blades(&self.config, &self.sampler, request.key, request.lod);
+        let data = if GRASS_INSTANCING {
+            let (x, z) = Self::settings().tile_center(request.key);
+            GrassTileData::Instanced(blades.iter().map(|blade| GrassInstance::new(blade, x, z)).collect())
+        } else {
+            let (mesh, grass_data) = generate_grass_mesh(&self.config, request.key, &blades);
+            GrassTileData::Baked(mesh, grass_data)
+        };
+        GrassTile { data, aabb: grass_tile_aabb(&blades) }
+    }
+
+    fn apply(world: &mut World, entity: Entity, request: TileRequest<u32>, tile: GrassTile) {
+        let GrassTile { data, aabb } = tile;
+        if !request.first {
+            // LOD change or edit: swap the blades and their bounds, everything else stays
+            world.entity_mut(entity).insert(aabb);
+            match data {
+                GrassTileData::Baked(mesh, _) => {
+                    let mesh_handle = world.resource_mut::<Assets<Mesh>>().add(mesh);
+                    world.entity_mut(entity).insert(mesh_handle);
+                }
+                GrassTileData::Instanced(instances) => {
+                    world.entity_mut(entity).insert(GrassInstances(instances.into()));
                 }
             }
+            return;
         }
 
-        // Helper closure to spawn grass tile asynchronously
-        let aabb = grass_tile_aabb();
-        let spawn_grass_async = |commands: &mut Commands, a: f32, b: f32| {
-            let transform = Transform::from_xyz(a, 0., b);
-            let task_entity = commands.spawn_empty().id();
-            let task = thread_pool.spawn(async move {
-                let mut command_queue = CommandQueue::default();
-                let (mesh, grass_data) = generate_grass_mesh(a, b, BLADES_PER_ROW, GRASS_TILE_SIZE);
-
-                command_queue.push(move |world: &mut World| {
-                    let (grass_mesh_handle, grass_mat_handle) = {
-                        let mut system_state = SystemState::<(ResMut<Assets<Mesh>>, ResMut<Assets<ExtendedMaterial<StandardMaterial,GrassMaterialExtension>>>)>::new(world);
-                        let (mut meshes, mut mats) = system_state.get_mut(world);
-
-                        (meshes.add(mesh), mats.add(ExtendedMaterial {
-                            base: grass_material(),
-                            extension: GrassMaterialExtension {}
-                        }))
-                    };
-
-                    world.entity_mut(task_entity)
+        let (x, z) = Self::settings().tile_center(request.key);
+        let transform = Transform::from_xyz(x, 0., z);
+
+        match data {
+            GrassTileData::Baked(mesh, grass_data) => {
+                let grass_mat_handle = world.resource::<GrassTileMaterial>().0.clone();
+                let grass_mesh_handle = world.resource_mut::<Assets<Mesh>>().add(mesh);
+
+                world.entity_mut(entity)
                     .insert(MaterialMeshBundle {
                         mesh: grass_mesh_handle,
                         material: grass_mat_handle,
                         transform,
                         ..default()
                     })
-                    .insert(Grass)
-                    .insert(grass_data)
-                    .insert(ContainsPlayer(false))
-                    .insert(aabb)
-                    .remove::<GenGrassTask>();
-                });
-
-                command_queue
-            });
-
-            commands.entity(task_entity).insert(GenGrassTask(task));
-        };
-
-        for (ent, _mh, _grass_data, grass_trans, _visibility, mut contains_player) in grass.iter_mut() {
-            // Distance-based despawn (completely remove from grid when too far)
-            if (player_trans.translation.x - grass_trans.translation.x).abs() > DESPAWN_DISTANCE
-                || (player_trans.translation.z - grass_trans.translation.z).abs() > DESPAWN_DISTANCE
-            {
-                grass_grid.0.remove(&(grass_trans.translation.x as i32, grass_trans.translation.z as i32));
-                commands.entity(ent).despawn_recursive();
-                continue;
-            }
-
-            // remove or add ContainsPlayer if applicable
-            if (player_trans.translation.x - grass_trans.translation.x).abs() >= GRASS_TILE_SIZE/2. || (player_trans.translation.z - grass_trans.translation.z).abs() >= GRASS_TILE_SIZE/2. {
-                if contains_player.0 {
-                    *contains_player = ContainsPlayer(false);
-                }
-            } else if !contains_player.0 {
-                *contains_player = ContainsPlayer(true);
-                // generate new grass tiles around the player
-                for i in -GRID_SIZE_HALF..=GRID_SIZE_HALF {
-                    for j in -GRID_SIZE_HALF..=GRID_SIZE_HALF {
-                        let a = grass_trans.translation.x + i as f32 * GRASS_TILE_SIZE;
-                        let b = grass_trans.translation.z + j as f32 * GRASS_TILE_SIZE;
-                        let tile_key = (a as i32, b as i32);
-
-                        // Only spawn if tile doesn't exist in grid
-                        let existing = grass_grid.0.get(&tile_key);
-                        let should_spawn = existing.is_none();
-
-                        if should_spawn {
-                            grass_grid.0.insert(tile_key, GrassTileState::pending());
-                            spawn_grass_async(&mut commands, a, b);
-                        }
-                    }
-                }
+                    .insert(grass_data);
             }
-            if contains_player.0 {
-                grass_w_player = Some(ent);
+            GrassTileData::Instanced(instances) => {
+                // The tile's own mesh is a single blade; the Aabb still covers the whole tile for culling
+                let blade_mesh = world.resource::<GrassBladeMesh>().0.clone();
+                world.entity_mut(entity)
+                    .insert(SpatialBundle::from_transform(transform))
+                    .insert(blade_mesh)
+                    .insert(GrassInstances(instances.into()));
             }
         }
+        world.entity_mut(entity)
+            .insert(Grass)
+            .insert(aabb);
+    }
+}
 
-        if let Some(_grass_w_player) = grass_w_player {
-            // // update aabb color
-            // commands.get_entity(grass_w_player).unwrap().insert(AabbGizmo {color: Some(Color::RED)});
-        }
+/// The material every baked grass tile shares, so the wind only needs updating in one place
+#[derive(Resource)]
+struct GrassTileMaterial(Handle<ExtendedMaterial<StandardMaterial, GrassMaterialExtension>>);
 
-    }
+fn setup_grass_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, GrassMaterialExtension>>>,
+    trail_map: Res<GrassTrailMap>,
+    wind: Res<Wind>,
+) {
+    let extension = GrassMaterialExtension { trail_map: trail_map.image.clone(), wind: wind.uniform() };
+    commands.insert_resource(GrassTileMaterial(materials.add(ExtendedMaterial { base: grass_material(), extension })));
 }
 
-fn handle_tasks(mut commands: Commands, mut grass_tasks: Query<&mut GenGrassTask>) {
-    for mut task in &mut grass_tasks {
-        if let Some(mut commands_queue) = block_on(poll_once(&mut task.0)) {
-            // append the returned command queue to have it execute later
-            commands.append(&mut commands_queue);
-        }
+fn update_grass_wind(
+    material: Res<GrassTileMaterial>,
+    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, GrassMaterialExtension>>>,
+    wind: Res<Wind>,
+) {
+    if let Some(material) = materials.get_mut(&material.0) {
+        material.extension.wind = wind.uniform();
     }
 }
 
-#[derive(Component)]
-struct GenGrassTask(Task<CommandQueue>);
+/// The blade every instanced grass tile draws
+#[derive(Resource)]
+struct GrassBladeMesh(Handle<Mesh>);
+
+/// One blade, one unit tall, centered on its base. grass_instanced.wgsl scales, bends and turns it per instance.
+fn setup_grass_blade_mesh(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[-GRASS_WIDTH / 2., 0., 0.], [GRASS_WIDTH / 2., 0., 0.], [0., 1., 0.]]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 1., 0.]; 3]);
+    mesh.insert_indices(mesh::Indices::U32(vec![0, 1, 2]));
+    commands.insert_resource(GrassBladeMesh(meshes.add(mesh)));
+}
 
 // 
 fn color_gradient_y_based(y: f32, rgba1: [f32; 4], rgba2: [f32; 4]) -> [f32;4] {
@@ -427,6 +472,12 @@ fn color_gradient_y_based(y: f32, rgba1: [f32; 4], rgba2: [f32; 4]) -> [f32;4] {
 
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct GrassMaterialExtension {
+    /// GrassTrailMap's texture, flattens blades where something has walked
+    #[texture(100, filterable = false)]
+    pub trail_map: Handle<Image>,
+    /// Wind::uniform, updated every frame
+    #[uniform(102)]
+    pub wind: WindUniform,
 }
 
 impl MaterialExtension for GrassMaterialExtension {
@@ -476,6 +527,90 @@ pub struct GrassPlugin;
 impl Plugin for GrassPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MaterialPlugin::<ExtendedMaterial<StandardMaterial,GrassMaterialExtension>>::default());
-        app.add_systems(Update, (update_grass,handle_tasks));
+        app.add_plugins((GrassTrailsPlugin, GrassInstancingPlugin, StreamingPlugin::<GrassLayer>::default()));
+        app.add_systems(Startup, (setup_grass_blade_mesh, setup_grass_material));
+        app.add_systems(Update, update_grass_wind.after(update_wind));
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::HashSet;
+
+    /// Whether a tile's area, [center - half, center + half) on each axis, contains (x, z)
+    fn tile_contains(key: TileKey, x: f32, z: f32) -> bool {
+        let (center_x, center_z) = GrassLayer::settings().tile_center(key);
+        let half = GRASS_TILE_SIZE / 2.0;
+        (center_x - half..center_x + half).contains(&x) && (center_z - half..center_z + half).contains(&z)
+    }
+
+    #[test]
+    fn walking_in_a_circle_never_overlaps_or_misses_tiles() {
+        let settings = GrassLayer::settings();
+        let covered = settings.radius as f32 * GRASS_TILE_SIZE;
+        // How far the slack tiles left loaded past the radius can reach
+        let reach = covered + 2.0 * GRASS_TILE_SIZE;
+        let steps = 360;
+        // What the grid has loaded, streamed the way stream_tiles does it
+        let mut loaded: HashSet<TileKey> = HashSet::new();
+        for step in 0..steps {
+            // A circle around a point off the origin, so the walk crosses both axes into negative coordinates
+            let angle = step as f32 / steps as f32 * TAU;
+            let (x, z) = (angle.cos() * 150.0 - 37.5, angle.sin() * 150.0 + 12.25);
+            let player_key = settings.world_to_tile(x, z);
+            assert!(tile_contains(player_key, x, z), "player at ({}, {}) isn't in their own tile", x, z);
+
+            let wanted: Vec<TileKey> = settings.wanted_tiles(player_key).map(|(key, _)| key).collect();
+            let tiles: HashSet<TileKey> = wanted.iter().copied().collect();
+            assert_eq!(tiles.len(), wanted.len(), "a tile is wanted twice with the player at ({}, {})", x, z);
+
+            // Tiles are despawned past the radius plus one tile of slack, and anything wanted but missing is spawned
+            loaded.retain(|key| settings.keeps(*key, player_key));
+            loaded.extend(tiles.iter().copied());
+
+            // No point is in two loaded tiles, slack included, and every point of the square kept around the player
+            // is in one of the wanted tiles
+            let spacing = GRASS_TILE_SIZE / 3.0 + 0.1;
+            let samples = (reach * 2.0 / spacing) as i32;
+            for i in 0..=samples {
+                for j in 0..=samples {
+                    let (px, pz) = (x - reach + i as f32 * spacing, z - reach + j as f32 * spacing);
+                    let (tile_x, tile_z) = settings.world_to_tile(px, pz);
+                    let containing = |set: &HashSet<TileKey>| (-1..=1)
+                        .flat_map(|dx| (-1..=1).map(move |dz| (tile_x + dx, tile_z + dz)))
+                        .filter(|key| set.contains(key) && tile_contains(*key, px, pz))
+                        .count();
+                    let in_loaded = containing(&loaded);
+                    assert!(in_loaded <= 1, "({}, {}) is in {} tiles with the player at ({}, {})", px, pz, in_loaded, x, z);
+                    if (px - x).abs() < covered && (pz - z).abs() < covered {
+                        let in_wanted = containing(&tiles);
+                        assert_eq!(in_wanted, 1, "({}, {}) is in {} tiles with the player at ({}, {})", px, pz, in_wanted, x, z);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blade_lattice_is_world_aligned_across_tiles() {
+        let settings = GrassLayer::settings();
+        let spacing = GRASS_TILE_SIZE / BLADES_PER_ROW as f32;
+        let mut previous = None;
+        for tile in -3..=3 {
+            let (center_x, center_z) = settings.tile_center((tile, -tile));
+            for i in 0..BLADES_PER_ROW {
+                let (lattice_x, lattice_z) = blade_lattice_point((tile, -tile), i, i);
+                // Where place_grass_blades puts the blade before jittering it
+                let x = center_x - GRASS_TILE_SIZE / 2.0 + i as f32 * spacing;
+                let z = center_z - GRASS_TILE_SIZE / 2.0 + i as f32 * spacing;
+                assert!((x - lattice_x as f32 * spacing).abs() < 1e-3 && (z - lattice_z as f32 * spacing).abs() < 1e-3);
+                // Consecutive along x, straight across tile borders, so no lattice point is used twice or skipped
+                if let Some(previous) = previous {
+                    assert_eq!(lattice_x, previous + 1);
+                }
+                previous = Some(lattice_x);
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::sync::Arc;
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::ecs::query::QueryItem;
+use bevy::ecs::system::{lifetimeless::*, SystemParamItem};
+use bevy::pbr::{MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup};
+use bevy::prelude::*;
+use bevy::render::extract_component::{ExtractComponent, ExtractComponentPlugin};
+use bevy::render::mesh::{GpuBufferInfo, MeshVertexBufferLayout};
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+    TrackedRenderPass,
+};
+use bevy::render::render_resource::binding_types::{texture_2d, uniform_buffer};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::ExtractedView;
+use bevy::render::{Render, RenderApp, RenderSet};
+use bevy::utils::HashMap;
+use bytemuck::{Pod, Zeroable};
+use super::grass::GrassBlade;
+use super::grass_trails::GrassTrailMap;
+use crate::util::wind::WindUniform;
+
+// Instance attributes start above every location the mesh pipeline can give to mesh attributes
+const INSTANCE_SHADER_LOCATION: u32 = 8;
+
+/// Per-blade data for the instanced grass path, read by grass_instanced.wgsl
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+#[repr(C)]
+pub struct GrassInstance {
+    /// World position of the blade's base
+    pub position: [f32; 3],
+    pub height: f32,
+    pub base_color: [f32; 4],
+    pub tip_color: [f32; 4],
+    pub rotation: f32,
+    pub color_shift: f32,
+    /// GrassBlade::lod_step, the shader fades the blade out past the distance for that step
+    pub lod_step: f32,
+    _padding: f32,
+}
+
+impl GrassInstance {
+    /// Instance for a blade placed on the tile centered at (tile_x, tile_z)
+    pub fn new(blade: &GrassBlade, tile_x: f32, tile_z: f32) -> Self {
+        Self {
+            position: [tile_x + blade.position.x, blade.position.y, tile_z + blade.position.z],
+            height: blade.height,
+            base_color: blade.base_color,
+            tip_color: blade.tip_color,
+            rotation: blade.rotation,
+            color_shift: blade.color_shift,
+            lod_step: blade.lod_step as f32,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Every blade of one grass tile. Drawn with the tile's mesh (a single blade) once per instance.
+/// Shared with the render world, so extracting it each frame is just a reference count.
+#[derive(Component, Clone)]
+pub struct GrassInstances(pub Arc<[GrassInstance]>);
+
+impl ExtractComponent for GrassInstances {
+    type QueryData = &'static GrassInstances;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+/// Draws GrassInstances entities with the instanced grass shader
+pub struct GrassInstancingPlugin;
+
+impl Plugin for GrassInstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractComponentPlugin::<GrassInstances>::default());
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Transparent3d, DrawGrassInstanced>()
+            .init_resource::<SpecializedMeshPipelines<GrassInstancePipeline>>()
+            .init_resource::<GrassInstanceBuffers>()
+            .init_resource::<GrassWindBuffer>()
+            .init_resource::<GrassBindGroup>()
+            .add_systems(
+                Render,
+                (
+                    queue_grass_instances.in_set(RenderSet::QueueMeshes),
+                    prepare_grass_instance_buffers.in_set(RenderSet::PrepareResources),
+                    prepare_grass_wind_buffer.in_set(RenderSet::PrepareResources),
+                    prepare_grass_bind_group.in_set(RenderSet::PrepareBindGroups),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp).init_resource::<GrassInstancePipeline>();
+    }
+}
+
+/// Queue every visible grass tile. Tiles culled by their Aabb have no RenderMeshInstance and are skipped.
+#[allow(clippy::too_many_arguments)]
+fn queue_grass_instances(
+    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    grass_pipeline: Res<GrassInstancePipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<GrassInstancePipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    grass_tiles: Query<Entity, With<GrassInstances>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_grass = transparent_3d_draw_functions.read().id::<DrawGrassInstanced>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+    for (view, mut transparent_phase) in &mut views {
+        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        let rangefinder = view.rangefinder3d();
+        for entity in &grass_tiles {
+            let Some(mesh_instance) = render_mesh_instances.get(&entity) else { continue };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else { continue };
+            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline = pipelines.specialize(&pipeline_cache, &grass_pipeline, key, &mesh.layout).unwrap();
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function: draw_grass,
+                distance: rangefinder.distance_translation(&mesh_instance.transforms.transform.translation),
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
+struct InstanceBuffer {
+    /// The data the buffer was made from, to tell when a tile has been regenerated
+    source: Arc<[GrassInstance]>,
+    buffer: Buffer,
+    length: usize,
+}
+
+/// GPU instance buffers by tile. A buffer is only uploaded when its tile appears or changes,
+/// and dropped once the tile is gone.
+#[derive(Resource, Default)]
+struct GrassInstanceBuffers(HashMap<Entity, InstanceBuffer>);
+
+fn prepare_grass_instance_buffers(
+    query: Query<(Entity, &GrassInstances)>,
+    mut buffers: ResMut<GrassInstanceBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    let mut alive = HashMap::new();
+    for (entity, instances) in &query {
+        let reusable = buffers.0.remove(&entity).filter(|existing| Arc::ptr_eq(&existing.source, &instances.0));
+        let buffer = reusable.unwrap_or_else(|| InstanceBuffer {
+            source: instances.0.clone(),
+            buffer: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("grass instance buffer"),
+                contents: bytemuck::cast_slice(&instances.0),
+                usage: BufferUsages::VERTEX,
+            }),
+            length: instances.0.len(),
+        });
+        alive.insert(entity, buffer);
+    }
+    buffers.0 = alive;
+}
+
+/// This frame's wind for the grass shader
+#[derive(Resource, Default)]
+struct GrassWindBuffer(UniformBuffer<WindUniform>);
+
+/// Same-sized writes go into the existing buffer, so the bind group made from it stays valid
+fn prepare_grass_wind_buffer(
+    wind: Option<Res<WindUniform>>,
+    mut buffer: ResMut<GrassWindBuffer>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(wind) = wind else { return };
+    buffer.0.set(*wind);
+    buffer.0.write_buffer(&render_device, &render_queue);
+}
+
+/// The trail map texture and the wind, bound after the mesh pipeline's view and mesh groups
+#[derive(Resource, Default)]
+struct GrassBindGroup(Option<BindGroup>);
+
+/// The trail texture and wind buffer are written in place and never replaced, so the bind group only needs making once
+fn prepare_grass_bind_group(
+    mut bind_group: ResMut<GrassBindGroup>,
+    pipeline: Res<GrassInstancePipeline>,
+    trail_map: Option<Res<GrassTrailMap>>,
+    wind_buffer: Res<GrassWindBuffer>,
+    images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    if bind_group.0.is_some() {
+        return;
+    }
+    let Some(trail_map) = trail_map else { return };
+    let Some(gpu_image) = images.get(&trail_map.image) else { return };
+    let Some(wind) = wind_buffer.0.binding() else { return };
+    bind_group.0 = Some(render_device.create_bind_group(
+        "grass_bind_group",
+        &pipeline.layout,
+        &BindGroupEntries::sequential((&gpu_image.texture_view, wind)),
+    ));
+}
+
+#[derive(Resource)]
+struct GrassInstancePipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+    layout: BindGroupLayout,
+}
+
+impl FromWorld for GrassInstancePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.resource::<AssetServer>().load("shaders/grass_instanced.wgsl");
+        let mesh_pipeline = world.resource::<MeshPipeline>().clone();
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            "grass_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    uniform_buffer::<WindUniform>(false),
+                ),
+            ),
+        );
+        GrassInstancePipeline { shader, mesh_pipeline, layout }
+    }
+}
+
+impl SpecializedMeshPipeline for GrassInstancePipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.layout.push(self.layout.clone());
+        let attribute = |format: VertexFormat, offset: u64, location: u32| VertexAttribute {
+            format,
+            offset,
+            shader_location: INSTANCE_SHADER_LOCATION + location,
+        };
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<GrassInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                attribute(VertexFormat::Float32x4, 0, 0),  // position, height
+                attribute(VertexFormat::Float32x4, 16, 1), // base_color
+                attribute(VertexFormat::Float32x4, 32, 2), // tip_color
+                attribute(VertexFormat::Float32x4, 48, 3), // rotation, color_shift, lod_step
+            ],
+        });
+        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+        // Blades are single triangles, seen from both sides
+        descriptor.primitive.cull_mode = None;
+        Ok(descriptor)
+    }
+}
+
+type DrawGrassInstanced = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetGrassBindGroup<2>,
+    DrawMeshInstanced,
+);
+
+struct SetGrassBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetGrassBindGroup<I> {
+    type Param = SRes<GrassBindGroup>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    #[inline]
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _item_query: Option<()>,
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = &bind_group.into_inner().0 else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+struct DrawMeshInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>, SRes<GrassInstanceBuffers>);
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        _item_query: Option<()>,
+        (meshes, render_mesh_instances, instance_buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(mesh_instance) = render_mesh_instances.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(instance_buffer) = instance_buffers.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instance_buffer.length as u32);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, 0..instance_buffer.length as u32);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
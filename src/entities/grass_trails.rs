@@ -0,0 +1,247 @@
+use std::sync::Arc;
+use bevy::prelude::*;
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy::render::render_asset::{RenderAssetUsages, RenderAssets};
+use bevy::render::render_resource::{Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, TextureAspect, TextureDimension, TextureFormat};
+use bevy::render::renderer::RenderQueue;
+use bevy::render::{Render, RenderApp, RenderSet};
+use crate::util::heightfield::TerrainSampler;
+
+// Trail map: a world-space texture of when the grass was flattened, around the player.
+// It wraps (texel = world texel mod size), so moving only clears the strips that scroll into view.
+// Trails recover in the shader from how long ago each texel was flattened, so only freshly stamped
+// and cleared texels are ever written and uploaded.
+// Keep the map size, texel size and recovery time in sync with grass_trails.wgsl
+const TRAIL_MAP_SIZE: i32 = 256;
+const TRAIL_TEXEL_SIZE: f32 = 0.5; // world units per texel, 128 units across
+const TRAIL_RECOVERY_TIME: f32 = 20.0; // seconds for a trail to spring back to about a third
+const TRAIL_MAX_HEIGHT: f32 = 3.0; // bodies further than this above the ground don't touch the grass
+const NEVER_FLATTENED: f32 = -1.0e9; // long enough ago that nothing is left of the trail
+
+/// Flattens grass within `radius` while on the ground. The player has one.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct DeformsGrass {
+    pub radius: f32,
+}
+
+/// The trail texture sampled by the grass shaders, and the texels changed since the last upload
+#[derive(Resource, Clone, ExtractResource)]
+pub struct GrassTrailMap {
+    pub image: Handle<Image>,
+    updates: Arc<[TrailUpdate]>,
+}
+
+/// A rectangle of the texture to overwrite
+#[derive(Clone)]
+struct TrailUpdate {
+    origin: UVec2,
+    size: UVec2,
+    texels: Vec<f32>,
+}
+
+/// When each texel was flattened, in the shaders' wrapped time (globals.time). A texel only part flattened
+/// is stored as the earlier time a full flattening would have recovered to the same amount, so one number
+/// holds both and the flatter trail is simply the later one.
+#[derive(Resource)]
+struct TrailState {
+    flattened_at: Vec<f32>,
+    /// World texel at the coverage's min corner
+    origin: IVec2,
+    /// Rectangles of world texels (max exclusive) written since the last upload
+    dirty: Vec<IRect>,
+    /// Time last frame, to notice it wrapping
+    time: f32,
+}
+
+impl TrailState {
+    fn index(texel: IVec2) -> usize {
+        (texel.y.rem_euclid(TRAIL_MAP_SIZE) * TRAIL_MAP_SIZE + texel.x.rem_euclid(TRAIL_MAP_SIZE)) as usize
+    }
+
+    /// Mark every texel of the coverage for upload
+    fn dirty_all(&mut self) {
+        self.dirty = vec![IRect::from_corners(self.origin, self.origin + TRAIL_MAP_SIZE)];
+    }
+
+    /// Move the coverage, clearing the rows and columns that now stand for a different part of the world
+    fn scroll_to(&mut self, origin: IVec2) {
+        let shift = origin - self.origin;
+        if shift.x.abs() >= TRAIL_MAP_SIZE || shift.y.abs() >= TRAIL_MAP_SIZE {
+            self.flattened_at.fill(NEVER_FLATTENED);
+            self.origin = origin;
+            self.dirty_all();
+            return;
+        }
+        let (old, new) = (self.origin, origin);
+        let leaving_x = if shift.x > 0 { old.x..new.x } else { new.x + TRAIL_MAP_SIZE..old.x + TRAIL_MAP_SIZE };
+        for x in leaving_x.clone() {
+            for z in 0..TRAIL_MAP_SIZE {
+                self.flattened_at[Self::index(IVec2::new(x, z))] = NEVER_FLATTENED;
+            }
+        }
+        let leaving_z = if shift.y > 0 { old.y..new.y } else { new.y + TRAIL_MAP_SIZE..old.y + TRAIL_MAP_SIZE };
+        for z in leaving_z.clone() {
+            for x in 0..TRAIL_MAP_SIZE {
+                self.flattened_at[Self::index(IVec2::new(x, z))] = NEVER_FLATTENED;
+            }
+        }
+        // The cleared strips now stand for the side of the new coverage they wrapped around to
+        let (columns, rows) = (TRAIL_MAP_SIZE * shift.x.signum(), TRAIL_MAP_SIZE * shift.y.signum());
+        if !leaving_x.is_empty() {
+            self.dirty.push(IRect::new(leaving_x.start + columns, new.y, leaving_x.end + columns, new.y + TRAIL_MAP_SIZE));
+        }
+        if !leaving_z.is_empty() {
+            self.dirty.push(IRect::new(new.x, leaving_z.start + rows, new.x + TRAIL_MAP_SIZE, leaving_z.end + rows));
+        }
+        self.origin = origin;
+    }
+
+    /// Flatten a disc at `time`, keeping whichever is flatter where trails overlap
+    fn stamp(&mut self, center: Vec2, radius: f32, time: f32) {
+        let texel_radius = (radius / TRAIL_TEXEL_SIZE).ceil() as i32;
+        let center_texel = (center / TRAIL_TEXEL_SIZE).floor().as_ivec2();
+        let coverage = IRect::from_corners(self.origin, self.origin + TRAIL_MAP_SIZE);
+        let disc = IRect::from_corners(center_texel - texel_radius, center_texel + texel_radius + 1).intersect(coverage);
+        if disc.is_empty() {
+            return;
+        }
+        for z in disc.min.y..disc.max.y {
+            for x in disc.min.x..disc.max.x {
+                let texel = IVec2::new(x, z);
+                let distance = ((texel.as_vec2() + 0.5) * TRAIL_TEXEL_SIZE).distance(center);
+                let amount = 1.0 - (distance / radius).powi(2);
+                if amount > 0.0 {
+                    let i = Self::index(texel);
+                    self.flattened_at[i] = self.flattened_at[i].max(time + TRAIL_RECOVERY_TIME * amount.ln());
+                }
+            }
+        }
+        self.dirty.push(disc);
+    }
+
+    /// Copy out the dirty rectangles as they sit in the wrapped texture, splitting any that cross its edge
+    fn take_updates(&mut self) -> Vec<TrailUpdate> {
+        let mut updates = Vec::new();
+        for rect in std::mem::take(&mut self.dirty) {
+            for (world_x, texture_x, width) in wrapped_spans(rect.min.x, rect.max.x) {
+                for (world_z, texture_z, height) in wrapped_spans(rect.min.y, rect.max.y) {
+                    let size = IVec2::new(width, height);
+                    let world_min = IVec2::new(world_x, world_z);
+                    let mut texels = Vec::with_capacity((size.x * size.y) as usize);
+                    for z in 0..size.y {
+                        for x in 0..size.x {
+                            texels.push(self.flattened_at[Self::index(world_min + IVec2::new(x, z))]);
+                        }
+                    }
+                    updates.push(TrailUpdate { origin: IVec2::new(texture_x, texture_z).as_uvec2(), size: size.as_uvec2(), texels });
+                }
+            }
+        }
+        updates
+    }
+}
+
+/// The texture rows or columns holding world texels start..end (at most TRAIL_MAP_SIZE of them),
+/// as one or two (world start, texture start, length) spans either side of the texture's edge
+fn wrapped_spans(start: i32, end: i32) -> Vec<(i32, i32, i32)> {
+    let texture_start = start.rem_euclid(TRAIL_MAP_SIZE);
+    let before_edge = TRAIL_MAP_SIZE - texture_start;
+    if end - start <= before_edge {
+        vec![(start, texture_start, end - start)]
+    } else {
+        vec![(start, texture_start, before_edge), (start + before_edge, 0, end - start - before_edge)]
+    }
+}
+
+/// Keeps grass_trails.wgsl loaded so the shaders importing it resolve
+#[derive(Resource)]
+struct GrassTrailsShader(#[allow(dead_code)] Handle<Shader>);
+
+fn setup_trail_map(mut commands: Commands, mut images: ResMut<Assets<Image>>, asset_server: Res<AssetServer>) {
+    let size = Extent3d { width: TRAIL_MAP_SIZE as u32, height: TRAIL_MAP_SIZE as u32, depth_or_array_layers: 1 };
+    // The shaders read texels with textureLoad and blend them themselves, 32-bit floats can't be filtered
+    let image = Image::new_fill(size, TextureDimension::D2, &NEVER_FLATTENED.to_le_bytes(), TextureFormat::R32Float, RenderAssetUsages::RENDER_WORLD);
+    commands.insert_resource(GrassTrailMap { image: images.add(image), updates: Arc::new([]) });
+    commands.insert_resource(TrailState {
+        flattened_at: vec![NEVER_FLATTENED; (TRAIL_MAP_SIZE * TRAIL_MAP_SIZE) as usize],
+        origin: IVec2::ZERO,
+        dirty: Vec::new(),
+        time: 0.0,
+    });
+    commands.insert_resource(GrassTrailsShader(asset_server.load("shaders/grass_trails.wgsl")));
+}
+
+/// Follow the player and flatten the grass under every DeformsGrass body
+fn update_trail_map(
+    mut state: ResMut<TrailState>,
+    mut trail_map: ResMut<GrassTrailMap>,
+    sampler: Res<TerrainSampler>,
+    deformers: Query<(&GlobalTransform, &DeformsGrass)>,
+    player: Query<&Transform, With<super::player::Player>>,
+    time: Res<Time>,
+) {
+    // Stamps are in the same wrapped seconds as globals.time. When that wraps, move them back by a period too.
+    let now = time.elapsed_seconds_wrapped();
+    if now < state.time {
+        let period = time.wrap_period().as_secs_f32();
+        for flattened_at in state.flattened_at.iter_mut() {
+            *flattened_at -= period;
+        }
+        state.dirty_all();
+    }
+    state.time = now;
+
+    if let Ok(player_trans) = player.get_single() {
+        let player_texel = (player_trans.translation.xz() / TRAIL_TEXEL_SIZE).floor().as_ivec2();
+        state.scroll_to(player_texel - IVec2::splat(TRAIL_MAP_SIZE / 2));
+        for (transform, deforms) in &deformers {
+            let position = transform.translation();
+            if position.y - sampler.height(position.x, position.z) < TRAIL_MAX_HEIGHT {
+                state.stamp(position.xz(), deforms.radius, now);
+            }
+        }
+    }
+
+    // Only touch the resource when there's something to upload, so it's only extracted then
+    if !state.dirty.is_empty() {
+        trail_map.updates = state.take_updates().into();
+    }
+}
+
+/// Write the changed texels straight into the existing texture, so materials' bind groups stay valid
+fn upload_trail_map(trail_map: Option<Res<GrassTrailMap>>, images: Res<RenderAssets<Image>>, render_queue: Res<RenderQueue>) {
+    let Some(trail_map) = trail_map else { return };
+    if !trail_map.is_changed() {
+        return;
+    }
+    let Some(gpu_image) = images.get(&trail_map.image) else { return };
+    for update in trail_map.updates.iter() {
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu_image.texture,
+                mip_level: 0,
+                origin: Origin3d { x: update.origin.x, y: update.origin.y, z: 0 },
+                aspect: TextureAspect::All,
+            },
+            bytemuck::cast_slice(&update.texels),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(update.size.x * 4),
+                rows_per_image: None,
+            },
+            Extent3d { width: update.size.x, height: update.size.y, depth_or_array_layers: 1 },
+        );
+    }
+}
+
+pub struct GrassTrailsPlugin;
+
+impl Plugin for GrassTrailsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractResourcePlugin::<GrassTrailMap>::default())
+            .add_systems(PreStartup, setup_trail_map)
+            .add_systems(Update, update_trail_map);
+        app.sub_app_mut(RenderApp)
+            .add_systems(Render, upload_trail_map.in_set(RenderSet::PrepareResources));
+    }
+}
@@ -1,8 +1,14 @@
 pub mod player;
 pub mod grass;
+pub mod grass_instancing;
+pub mod grass_trails;
 #[allow(clippy::eq_op)]
 pub mod terrain;
 pub mod tree;
+pub mod tree_felling;
+pub mod tree_impostor;
+pub mod tree_index;
+pub mod tree_species;
 // pub mod enemy;
 // pub mod projectiles;
 // pub mod poi;
\ No newline at end of file
@@ -3,8 +3,8 @@ use std::f32::consts::TAU;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use bevy_rapier3d::control::KinematicCharacterController;
-use noise::NoiseFn;
-use crate::util::{gravity::{GRAVITY_ACC, GRAVITY_DIR}, perlin::PerlinNoiseEntity};
+use crate::entities::grass_trails::DeformsGrass;
+use crate::util::{gravity::{GRAVITY_ACC, GRAVITY_DIR}, height_delta::{Brush, DeformTerrain}, heightfield::TerrainSampler, wind::Wind};
 
 const SPEED: f32 = 400.0;
 const ROTATION_SPEED: f32 = 0.3;
@@ -16,14 +16,24 @@ const RUN_COEFF: f32 = 3.0;
 pub const SPAWN_TRANSFORM: Transform = Transform::from_xyz(0.0, 200. + PLAYER_HEIGHT + 5., 0.0);
 const TORCH_INTENSITY: f32 = 10_000_000.;
 const FLICKER_SPEED: f64 = 2.;
-// struct for marking terrain that contains the player
-#[derive(Component)]
-pub struct ContainsPlayer(pub bool);
+// Swimming
+const SWIM_DEPTH: f32 = 0.6; // fraction of the player below the surface before they start swimming
+const SWIM_SPEED_COEFF: f32 = 0.3; // horizontal speed while swimming, relative to walking
+const SWIM_VERTICAL_SPEED: f32 = 6.0; // speed when swimming up (Space) or diving (ControlLeft)
+const BUOYANCY: f32 = 2.0; // how quickly the player floats back up to the surface, per second
+const FLOAT_HEIGHT: f32 = 0.3; // fraction of the player held above the surface when floating
+// Digging: R raises, G digs, T flattens to the height the player stands at, Y smooths
+const DIG_DISTANCE: f32 = 4.0; // brush center in front of the player
+const DIG_RADIUS: f32 = 3.0;
+const DIG_SPEED: f32 = 2.0; // world units per second at the brush center
+const DIG_SMOOTHING: f32 = 3.0; // fraction of the way to flat or smooth per second
 
 #[derive(Reflect, Component, Default, Debug)]
 #[reflect(Component)]
 pub struct Player {
-    shooting_timer: Timer
+    shooting_timer: Timer,
+    /// Deep enough in water to swim rather than walk
+    pub swimming: bool,
 }
 
 #[derive(Component)]
@@ -55,7 +65,8 @@ pub fn setup_player(
     .insert(RigidBody::KinematicPositionBased)
     .insert(Collider::cuboid(PLAYER_WIDTH/2.0, PLAYER_HEIGHT/2.0, PLAYER_WIDTH/2.0))
     .insert(KinematicCharacterController::default())
-    .insert(Player { shooting_timer: Timer::from_seconds(FIRE_RATE, TimerMode::Repeating) })
+    .insert(Player { shooting_timer: Timer::from_seconds(FIRE_RATE, TimerMode::Repeating), swimming: false })
+    .insert(DeformsGrass { radius: PLAYER_WIDTH })
     .add_child(light)
     .insert(Name::new("Player"));
 }
@@ -67,13 +78,14 @@ fn player_movement(
     _materials: ResMut<Assets<StandardMaterial>>,
     mut player: Query<(&mut Player, &mut Transform, &mut KinematicCharacterController)>,
     // _enemies: Query<&GlobalTransform, With<ent::enemy::Enemy>>,
+    sampler: Res<TerrainSampler>,
     time: Res<Time>
 ) {
     let base_movement = GRAVITY_ACC*GRAVITY_DIR*time.delta_seconds();
     let mut movement = Vec3::ZERO;
     let mut rotation = 0.;
     if let Ok(player) = player.get_single_mut() {
-        let (_player, mut plyr_trans, mut controller) = player;
+        let (mut player, mut plyr_trans, mut controller) = player;
         // // shooting
         // player.shooting_timer.tick(time.delta());
         // if player.shooting_timer.just_finished() {
@@ -97,7 +109,15 @@ fn player_movement(
             movement += plyr_trans.rotation * Vec3::X * SPEED * time.delta_seconds();
         }
 
-        if keys.pressed(KeyCode::ShiftLeft) {
+        // Swimming once enough of the player is under the water surface (sea, lake or river)
+        let water_level = sampler.water_level(plyr_trans.translation.x, plyr_trans.translation.z);
+        let feet = plyr_trans.translation.y - PLAYER_HEIGHT / 2.;
+        let submersion = ((water_level - feet) / PLAYER_HEIGHT).clamp(0., 1.);
+        player.swimming = submersion > SWIM_DEPTH;
+
+        if player.swimming {
+            movement *= SWIM_SPEED_COEFF;
+        } else if keys.pressed(KeyCode::ShiftLeft) {
             movement *= RUN_COEFF;
         }
 
@@ -112,6 +132,22 @@ fn player_movement(
             plyr_trans.rotate_y(rotation);
         }
 
+        if player.swimming {
+            // Buoyancy replaces gravity: float back up until the head is above the surface,
+            // unless the player is swimming up or diving
+            let float_y = water_level - PLAYER_HEIGHT / 2. + PLAYER_HEIGHT * FLOAT_HEIGHT;
+            let mut vertical = (float_y - plyr_trans.translation.y) * BUOYANCY;
+            if keys.pressed(KeyCode::Space) {
+                vertical = SWIM_VERTICAL_SPEED;
+            }
+            if keys.pressed(KeyCode::ControlLeft) {
+                vertical = -SWIM_VERTICAL_SPEED;
+            }
+            movement += vertical * time.delta_seconds() * Vec3::Y;
+            controller.translation = Some(movement);
+            return;
+        }
+
         // Creative mode flying. Removes gravity effect
         if keys.pressed(KeyCode::ShiftLeft) {
             movement = movement + JUMP_HEIGHT*time.delta_seconds()*Vec3::Y - base_movement;
@@ -124,14 +160,39 @@ fn player_movement(
     }
 }
 
+/// Reshape the ground in front of the player while a digging key is held
+fn player_digging(
+    keys: Res<ButtonInput<KeyCode>>,
+    player: Query<&Transform, With<Player>>,
+    sampler: Res<TerrainSampler>,
+    mut deform: EventWriter<DeformTerrain>,
+    time: Res<Time>,
+) {
+    let Ok(plyr_trans) = player.get_single() else { return };
+    let dt = time.delta_seconds();
+    let (brush, strength) = if keys.pressed(KeyCode::KeyR) {
+        (Brush::Raise, DIG_SPEED * dt)
+    } else if keys.pressed(KeyCode::KeyG) {
+        (Brush::Lower, DIG_SPEED * dt)
+    } else if keys.pressed(KeyCode::KeyT) {
+        (Brush::Flatten(sampler.height(plyr_trans.translation.x, plyr_trans.translation.z)), DIG_SMOOTHING * dt)
+    } else if keys.pressed(KeyCode::KeyY) {
+        (Brush::Smooth, DIG_SMOOTHING * dt)
+    } else {
+        return;
+    };
+    let center = plyr_trans.translation + plyr_trans.rotation * -Vec3::Z * DIG_DISTANCE;
+    deform.send(DeformTerrain { brush, center: center.xz(), radius: DIG_RADIUS, strength });
+}
+
 fn torch_system(
     mut torch_query: Query<&mut PointLight, With<Torch>>,
-    perlin: Res<PerlinNoiseEntity>,
+    wind: Res<Wind>,
     time: Res<Time>,
 ) {
     if let Ok(mut torch) = torch_query.get_single_mut() {
-        let flicker = (1. + perlin.wind.get([time.elapsed_seconds_f64()*FLICKER_SPEED, time.elapsed_seconds_f64()*FLICKER_SPEED]))/2.;
-        torch.intensity = TORCH_INTENSITY * flicker as f32;
+        let flicker = (1. + wind.flutter(time.elapsed_seconds_f64()*FLICKER_SPEED))/2.;
+        torch.intensity = TORCH_INTENSITY * flicker;
         
     }
 }
@@ -143,6 +204,7 @@ impl Plugin for PlayerPlugin {
         app.add_systems(Startup, setup_player);
         app.add_systems(Update, (
             player_movement,
+            player_digging,
             torch_system
         ));
     }
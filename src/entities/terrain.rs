@@ -1,18 +1,22 @@
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
 use bevy::render::texture::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor};
 use bevy::{prelude::*, render::render_resource::Face};
 use bevy::math::Vec3A;
-use bevy::render::mesh::VertexAttributeValues;
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy::render::render_resource::PrimitiveTopology;
 use bevy::render::mesh::Indices;
 use bevy::render::primitives::Aabb;
-use bevy::utils::HashMap;
-use bevy::ecs::system::{CommandQueue, SystemState};
+use bevy::ecs::system::SystemState;
 use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
 use futures_lite::future::poll_once;
 use crate::entities::player;
-use crate::util::perlin::{self, sample_terrain_height};
-use crate::util::render_state::RenderState;
+use crate::util::biome;
+use crate::util::heightfield::{HeightSampler, TerrainSampler};
+use crate::util::streaming::{tile_distance, LayerGenerator, StreamingPlugin, StreamingSettings, TileKey, TileRequest};
+use crate::util::height_delta::TerrainEdited;
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
 use bevy_rapier3d::prelude::*;
 
 // Chunk configuration
@@ -20,7 +24,7 @@ pub const CHUNK_SIZE: f32 = 512.; // Size of each terrain chunk
 const CHUNKS_RADIUS: i32 = 12; // How many chunks in each direction from player
 
 // LOD levels - subdivisions decrease with distance
-const LOD_0_SUBDIVISIONS: u32 = 64; // Highest detail (close to player)
+pub const LOD_0_SUBDIVISIONS: u32 = 64; // Highest detail (close to player)
 const LOD_1_SUBDIVISIONS: u32 = 32;
 const LOD_2_SUBDIVISIONS: u32 = 16;
 const LOD_3_SUBDIVISIONS: u32 = 8; // Lowest detail (far from player)
@@ -33,27 +37,24 @@ const LOD_3_DISTANCE: i32 = 9;
 // Distance threshold for physics colliders (in chunks) - needs to be large enough
 // to ensure colliders exist before the player reaches them
 const COLLIDER_DISTANCE: i32 = 2;
+// Heightfield collider resolution, independent of the chunk's render LOD
+const COLLIDER_SUBDIVISIONS: usize = 64;
 
 // Maximum number of terrain chunks to spawn per frame (rate limiting)
 const MAX_CHUNKS_PER_FRAME: usize = 8;
 
 const TILE_WIDTH: u32 = 16; // how wide a tile should be
 const TEXTURE_SCALE: f32 = 7.;
+// Keep in sync with WATER_TEXTURE_SCALE in water_shader.wgsl
 const WATER_TEXTURE_SCALE: f32 = 20.;
-pub const BASE_LEVEL: f32 = 200.;
-pub const WATER_LEVEL: f32 = 189.;
-const WATER_SCROLL_SPEED: f32 = 0.0002;
-const HEIGHT_PEAKS: f32 = 1500.;
-const HEIGHT_SAND: f32 = 200.;
-pub const HEIGHT_TEMPERATE_START: f32 = 210.;
-pub const HEIGHT_TEMPERATE_END: f32 = 800.;
-const COLOR_TEMPERATE: [f32;4] = [0.079,0.079,0.,1.];
-const COLOR_SAND: [f32;4] = [80./255., 72./255., 49./255., 255./255.];
-const COLOR_PEAKS: [f32;4] = [255./255.,255./255.,255./255.,255./255.];
+// Resolution of the per-chunk lake and river surface
+const WATER_SUBDIVISIONS: u32 = 32;
+// Inland water has to sit at least this far above the sea to get its own surface
+const INLAND_WATER_MIN_HEIGHT: f32 = 0.01;
 
 /// Terrain height range for Aabb calculation (min to max possible height)
-const TERRAIN_MIN_HEIGHT: f32 = 0.0;
-const TERRAIN_MAX_HEIGHT: f32 = 1500.0; // HEIGHT_PEAKS approximation
+pub const TERRAIN_MIN_HEIGHT: f32 = -600.0; // the default noise stack's ocean floor bottoms out around -510
+pub const TERRAIN_MAX_HEIGHT: f32 = 1500.0; // HEIGHT_PEAKS approximation
 
 // Terrain chunk component
 #[derive(Component)]
@@ -61,35 +62,33 @@ pub struct Terrain {
     pub chunk_x: i32,
     pub chunk_z: i32,
     pub lod_level: u32,
+    pub edge_lods: NeighbourLods,
 }
 
-/// Terrain-specific chunk state that wraps the unified RenderState
+/// Subdivisions of the four chunks bordering a chunk.
+/// Edges facing a coarser neighbour are stitched to it so there are no T-junction cracks.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub struct TerrainChunkState {
-    pub render_state: RenderState,
-    pub lod_level: u32,
+pub struct NeighbourLods {
+    pub neg_x: u32,
+    pub pos_x: u32,
+    pub neg_z: u32,
+    pub pos_z: u32,
 }
 
-impl TerrainChunkState {
-    pub fn visible(lod_level: u32) -> Self {
-        Self { render_state: RenderState::Visible, lod_level }
-    }
-
-    pub fn pending() -> Self {
-        Self { render_state: RenderState::Pending, lod_level: 0 }
-    }
+/// Render LOD of a chunk: its own subdivisions and those of the neighbours it stitches to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TerrainLod {
+    pub subdivisions: u32,
+    pub edge_lods: NeighbourLods,
 }
 
-#[derive(Component)]
-pub struct TerrainGrid(pub HashMap<(i32, i32), TerrainChunkState>);
-
 // Component to mark chunk with physics collider (only close chunks get colliders)
 #[derive(Component)]
 pub struct TerrainCollider;
 
-// Component for async terrain generation task
+// Component for async collider generation on an existing chunk (player approached)
 #[derive(Component)]
-struct GenTerrainTask(Task<CommandQueue>);
+struct GenColliderTask(Task<Collider>);
 
 /// Calculate the LOD level based on chunk distance from player
 fn get_lod_level(chunk_distance: i32) -> u32 {
@@ -104,6 +103,20 @@ fn get_lod_level(chunk_distance: i32) -> u32 {
     }
 }
 
+/// Calculate the LOD levels of the chunks bordering (chunk_x, chunk_z)
+fn get_neighbour_lods(chunk_x: i32, chunk_z: i32, player_chunk_x: i32, player_chunk_z: i32) -> NeighbourLods {
+    let lod_at = |x: i32, z: i32| {
+        let chunk_distance = (x - player_chunk_x).abs().max((z - player_chunk_z).abs());
+        get_lod_level(chunk_distance)
+    };
+    NeighbourLods {
+        neg_x: lod_at(chunk_x - 1, chunk_z),
+        pos_x: lod_at(chunk_x + 1, chunk_z),
+        neg_z: lod_at(chunk_x, chunk_z - 1),
+        pos_z: lod_at(chunk_x, chunk_z + 1),
+    }
+}
+
 /// Create an Aabb for a terrain chunk (centered at local origin)
 fn terrain_chunk_aabb() -> Aabb {
     let half_size = CHUNK_SIZE / 2.0;
@@ -115,424 +128,260 @@ fn terrain_chunk_aabb() -> Aabb {
     }
 }
 
-/// Convert world position to chunk coordinates
-fn world_to_chunk(x: f32, z: f32) -> (i32, i32) {
-    (
-        (x / CHUNK_SIZE).floor() as i32,
-        (z / CHUNK_SIZE).floor() as i32,
-    )
+/// Convert world position to chunk coordinates (the same lattice the chunks are streamed on)
+fn world_to_chunk(x: f32, z: f32) -> TileKey {
+    TerrainLayer::settings().world_to_tile(x, z)
 }
 
 /// Convert chunk coordinates to world position (center of chunk)
-fn chunk_to_world(chunk_x: i32, chunk_z: i32) -> (f32, f32) {
+pub fn chunk_to_world(chunk_x: i32, chunk_z: i32) -> (f32, f32) {
     (
         chunk_x as f32 * CHUNK_SIZE + CHUNK_SIZE / 2.0,
         chunk_z as f32 * CHUNK_SIZE + CHUNK_SIZE / 2.0,
     )
 }
 
-/// Main terrain update system
-#[allow(clippy::too_many_arguments)]
-pub fn update_terrain(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-    terrain_chunks: Query<(Entity, &Terrain, &Handle<Mesh>, Option<&TerrainCollider>)>,
-    mut grid_query: Query<&mut TerrainGrid>,
-    player: Query<&Transform, With<player::Player>>,
-) {
-    let Ok(player_trans) = player.get_single() else { return };
-    let player_pos = player_trans.translation;
-    let (player_chunk_x, player_chunk_z) = world_to_chunk(player_pos.x, player_pos.z);
-
-    if terrain_chunks.is_empty() {
-        // Initial spawn - create terrain grid and spawn chunks
-        let mut terrain_grid = TerrainGrid(HashMap::new());
-
-        for dx in -CHUNKS_RADIUS..=CHUNKS_RADIUS {
-            for dz in -CHUNKS_RADIUS..=CHUNKS_RADIUS {
-                let chunk_x = player_chunk_x + dx;
-                let chunk_z = player_chunk_z + dz;
-
-                // Calculate distance for LOD
-                let chunk_distance = dx.abs().max(dz.abs());
-                let lod_level = get_lod_level(chunk_distance);
-
-                // Spawn chunks with colliders synchronously (player needs them immediately)
-                // Spawn distant chunks asynchronously
-                if chunk_distance <= COLLIDER_DISTANCE {
-                    terrain_grid.0.insert((chunk_x, chunk_z), TerrainChunkState::visible(lod_level));
-                    spawn_terrain_chunk(
-                        &mut commands,
-                        &mut meshes,
-                        &mut materials,
-                        chunk_x,
-                        chunk_z,
-                        lod_level,
-                        true,
-                    );
-                } else {
-                    terrain_grid.0.insert((chunk_x, chunk_z), TerrainChunkState::pending());
-                    spawn_terrain_chunk_async(
-                        &mut commands,
-                        chunk_x,
-                        chunk_z,
-                        lod_level,
-                        false,
-                    );
-                }
-            }
-        }
-
-        commands.spawn(terrain_grid);
-        spawn_water_plane(&mut commands, &mut meshes, &mut materials, &asset_server);
-    } else {
-        // Main update logic
-        let Ok(mut terrain_grid) = grid_query.get_single_mut() else { return };
-
-        // Update grid state for completed async tasks (Pending -> Visible)
-        for (_, terrain, _, _) in terrain_chunks.iter() {
-            if let Some(state) = terrain_grid.0.get(&(terrain.chunk_x, terrain.chunk_z)) {
-                if state.render_state == RenderState::Pending {
-                    terrain_grid.0.insert((terrain.chunk_x, terrain.chunk_z), TerrainChunkState::visible(terrain.lod_level));
-                }
-            }
-        }
+/// A generated chunk, ready to go into the world
+pub struct TerrainChunk {
+    mesh: Mesh,
+    collider: Option<Collider>,
+    water: Option<Mesh>,
+}
 
-        // Track chunks to despawn (too far) or update (LOD/collider change)
-        let mut chunks_to_despawn: Vec<Entity> = Vec::new();
-        let mut chunks_to_update: Vec<(Entity, i32, i32, u32, Handle<Mesh>)> = Vec::new();
-        // Track chunks that need collider added/removed (without LOD change)
-        let mut chunks_need_collider: Vec<(Entity, Handle<Mesh>)> = Vec::new();
-        let mut chunks_remove_collider: Vec<Entity> = Vec::new();
-
-        // Check existing chunks for despawn/LOD update/collider update
-        for (entity, terrain, mesh_handle, has_collider) in terrain_chunks.iter() {
-            let dx = terrain.chunk_x - player_chunk_x;
-            let dz = terrain.chunk_z - player_chunk_z;
-            let chunk_distance = dx.abs().max(dz.abs());
-
-            // Despawn if too far (beyond render distance)
-            if chunk_distance > CHUNKS_RADIUS {
-                terrain_grid.0.remove(&(terrain.chunk_x, terrain.chunk_z));
-                chunks_to_despawn.push(entity);
-                continue;
-            }
+/// Streams terrain chunks around the player (see util::streaming)
+pub struct TerrainLayer {
+    config: WorldGenConfig,
+    sampler: TerrainSampler,
+}
 
-            // Check if LOD needs to change
-            let new_lod = get_lod_level(chunk_distance);
-            if new_lod != terrain.lod_level {
-                chunks_to_update.push((entity, terrain.chunk_x, terrain.chunk_z, new_lod, mesh_handle.clone()));
-            } else {
-                // LOD unchanged, but check if collider state needs to change
-                let needs_collider = chunk_distance <= COLLIDER_DISTANCE;
-                if needs_collider && has_collider.is_none() {
-                    chunks_need_collider.push((entity, mesh_handle.clone()));
-                } else if !needs_collider && has_collider.is_some() {
-                    chunks_remove_collider.push(entity);
-                }
-            }
-        }
+impl LayerGenerator for TerrainLayer {
+    type Lod = TerrainLod;
+    type Output = TerrainChunk;
 
-        // Despawn chunks that are too far
-        for entity in chunks_to_despawn {
-            commands.entity(entity).despawn_recursive();
+    fn settings() -> StreamingSettings {
+        StreamingSettings {
+            tile_size: CHUNK_SIZE,
+            radius: CHUNKS_RADIUS,
+            max_spawns_per_frame: MAX_CHUNKS_PER_FRAME,
         }
+    }
 
-        // Update LOD for existing chunks
-        for (entity, chunk_x, chunk_z, new_lod, mesh_handle) in chunks_to_update {
-            let (world_x, world_z) = chunk_to_world(chunk_x, chunk_z);
-            let new_mesh = generate_terrain_mesh(world_x, world_z, CHUNK_SIZE, new_lod);
-
-            if let Some(mesh) = meshes.get_mut(&mesh_handle) {
-                *mesh = new_mesh.clone();
-            }
-
-            let dx = chunk_x - player_chunk_x;
-            let dz = chunk_z - player_chunk_z;
-            let chunk_distance = dx.abs().max(dz.abs());
-
-            // Update collider if close enough
-            let mut entity_commands = commands.entity(entity);
-            entity_commands.insert(Terrain {
-                chunk_x,
-                chunk_z,
-                lod_level: new_lod,
-            });
-
-            if chunk_distance <= COLLIDER_DISTANCE {
-                let collider_shape = ComputedColliderShape::TriMesh;
-                if let Some(collider) = Collider::from_bevy_mesh(&new_mesh, &collider_shape) {
-                    entity_commands.insert(collider);
-                    entity_commands.insert(TerrainCollider);
-                }
-            } else {
-                entity_commands.remove::<Collider>();
-                entity_commands.remove::<TerrainCollider>();
-            }
+    fn new(config: &WorldGenConfig, sampler: &TerrainSampler, _deltas: &WorldDeltas) -> Self {
+        Self { config: config.clone(), sampler: sampler.clone() }
+    }
 
-            terrain_grid.0.insert((chunk_x, chunk_z), TerrainChunkState::visible(new_lod));
+    fn lod(key: TileKey, player_key: TileKey) -> TerrainLod {
+        TerrainLod {
+            subdivisions: get_lod_level(tile_distance(key, player_key)),
+            edge_lods: get_neighbour_lods(key.0, key.1, player_key.0, player_key.1),
         }
+    }
 
-        // Add colliders to chunks that now need them (player approached)
-        for (entity, mesh_handle) in chunks_need_collider {
-            if let Some(mesh) = meshes.get(&mesh_handle) {
-                let collider_shape = ComputedColliderShape::TriMesh;
-                if let Some(collider) = Collider::from_bevy_mesh(mesh, &collider_shape) {
-                    commands.entity(entity).insert(collider).insert(TerrainCollider);
-                }
-            }
-        }
+    // Chunks close enough to need a collider never wait behind the rate limit
+    fn urgent(distance: i32) -> bool {
+        distance <= COLLIDER_DISTANCE
+    }
 
-        // Remove colliders from chunks that no longer need them (player moved away)
-        for entity in chunks_remove_collider {
-            commands.entity(entity).remove::<Collider>().remove::<TerrainCollider>();
-        }
+    fn generate(&self, request: &TileRequest<TerrainLod>) -> TerrainChunk {
+        let (world_x, world_z) = chunk_to_world(request.key.0, request.key.1);
+        let mesh = generate_terrain_mesh(&self.config, &self.sampler, world_x, world_z, CHUNK_SIZE, request.lod.subdivisions, request.lod.edge_lods);
+        // Colliders and water don't depend on LOD, so they're only built the first time.
+        // Edits can move the water, edited chunks get theirs rebuilt (refresh_edited_colliders does the colliders)
+        let with_collider = request.first && request.distance <= COLLIDER_DISTANCE;
+        let collider = if with_collider { Some(generate_terrain_collider(&self.sampler, world_x, world_z, CHUNK_SIZE)) } else { None };
+        let with_water = request.first || request.edited;
+        let water = if with_water { generate_inland_water_mesh(&self.sampler, world_x, world_z, CHUNK_SIZE) } else { None };
+        TerrainChunk { mesh, collider, water }
+    }
 
-        // Collect chunks that need to be spawned
-        let mut chunks_to_spawn: Vec<(i32, i32, i32, u32, bool)> = Vec::new(); // (chunk_x, chunk_z, distance, lod, needs_collider)
-
-        for dx in -CHUNKS_RADIUS..=CHUNKS_RADIUS {
-            for dz in -CHUNKS_RADIUS..=CHUNKS_RADIUS {
-                let chunk_x = player_chunk_x + dx;
-                let chunk_z = player_chunk_z + dz;
-
-                // Check if chunk already exists or is being handled
-                let existing_state = terrain_grid.0.get(&(chunk_x, chunk_z));
-
-                match existing_state {
-                    None => {
-                        // New chunk - queue for spawn
-                        let chunk_distance = dx.abs().max(dz.abs());
-                        let lod_level = get_lod_level(chunk_distance);
-                        let needs_collider = chunk_distance <= COLLIDER_DISTANCE;
-                        chunks_to_spawn.push((chunk_x, chunk_z, chunk_distance, lod_level, needs_collider));
-                    }
-                    Some(state) if state.render_state == RenderState::Pending => {
-                        // Already being generated - nothing to do
-                    }
-                    Some(state) if state.render_state == RenderState::Visible => {
-                        // Already visible - nothing to do
-                    }
-                    _ => {}
-                }
+    fn apply(world: &mut World, entity: Entity, request: TileRequest<TerrainLod>, chunk: TerrainChunk) {
+        let (chunk_x, chunk_z) = request.key;
+        let terrain = Terrain {
+            chunk_x,
+            chunk_z,
+            lod_level: request.lod.subdivisions,
+            edge_lods: request.lod.edge_lods,
+        };
+
+        if !request.first {
+            // LOD change or edit: swap the mesh, and the water if it was rebuilt
+            let mesh_handle = world.resource_mut::<Assets<Mesh>>().add(chunk.mesh);
+            world.entity_mut(entity).insert(mesh_handle).insert(terrain);
+            if request.edited {
+                replace_inland_water(world, entity, chunk.water);
             }
+            return;
         }
 
-        // Sort chunks by distance (spawn closer chunks first, prioritize chunks needing colliders)
-        chunks_to_spawn.sort_by_key(|(_, _, distance, _, needs_collider)| {
-            // Chunks needing colliders get highest priority (negative distance)
-            if *needs_collider { -100 + *distance } else { *distance }
-        });
-
-        // Spawn chunks with rate limiting
-        let mut async_spawns_this_frame = 0;
-        for (chunk_x, chunk_z, _distance, lod_level, needs_collider) in chunks_to_spawn {
-            if needs_collider {
-                // Spawn synchronously - player might need this for physics
-                terrain_grid.0.insert((chunk_x, chunk_z), TerrainChunkState::visible(lod_level));
-                spawn_terrain_chunk(
-                    &mut commands,
-                    &mut meshes,
-                    &mut materials,
-                    chunk_x,
-                    chunk_z,
-                    lod_level,
-                    true,
-                );
-            } else if async_spawns_this_frame < MAX_CHUNKS_PER_FRAME {
-                // Spawn asynchronously with rate limiting
-                terrain_grid.0.insert((chunk_x, chunk_z), TerrainChunkState::pending());
-                spawn_terrain_chunk_async(
-                    &mut commands,
-                    chunk_x,
-                    chunk_z,
-                    lod_level,
-                    false,
-                );
-                async_spawns_this_frame += 1;
-            }
-            // Chunks beyond the rate limit will be picked up next frame
+        let (mesh_handle, material_handle) = {
+            let mut system_state = SystemState::<(
+                ResMut<Assets<Mesh>>,
+                ResMut<Assets<StandardMaterial>>,
+            )>::new(world);
+            let (mut meshes, mut materials) = system_state.get_mut(world);
+
+            let terrain_material = StandardMaterial {
+                alpha_mode: AlphaMode::Opaque,
+                double_sided: true,
+                perceptual_roughness: 1.0,
+                reflectance: 0.4,
+                cull_mode: Some(Face::Back),
+                flip_normal_map_y: true,
+                ..default()
+            };
+
+            (meshes.add(chunk.mesh), materials.add(terrain_material))
+        };
+
+        let (world_x, world_z) = chunk_to_world(chunk_x, chunk_z);
+        let mut chunk_entity = world.entity_mut(entity);
+        chunk_entity
+            .insert(PbrBundle {
+                mesh: mesh_handle,
+                material: material_handle,
+                transform: Transform::from_xyz(world_x, 0., world_z),
+                ..default()
+            })
+            .insert(terrain)
+            .insert(terrain_chunk_aabb());
+
+        // Add collider if needed
+        if let Some(collider) = chunk.collider {
+            chunk_entity.insert(collider);
+            chunk_entity.insert(TerrainCollider);
         }
 
-        // Clean up grid entries for chunks that are too far
-        terrain_grid.0.retain(|(cx, cz), _| {
-            let dx = (*cx - player_chunk_x).abs();
-            let dz = (*cz - player_chunk_z).abs();
-            dx <= CHUNKS_RADIUS + 1 && dz <= CHUNKS_RADIUS + 1
-        });
+        replace_inland_water(world, entity, chunk.water);
     }
 }
 
-fn get_terrain_color(y: f32) -> [f32;4] {
-    if y < HEIGHT_SAND { COLOR_SAND }
-    else if y > HEIGHT_PEAKS { COLOR_PEAKS }
-    else if y < HEIGHT_TEMPERATE_START {
-        terrain_color_gradient(
-            (y-HEIGHT_SAND)/(HEIGHT_TEMPERATE_START-HEIGHT_SAND),
-            COLOR_SAND,
-            COLOR_TEMPERATE
-        )
-    } else if y < HEIGHT_TEMPERATE_END {
-        COLOR_TEMPERATE
-    } else {
-        terrain_color_gradient(
-            (y-HEIGHT_TEMPERATE_END)/(HEIGHT_PEAKS-HEIGHT_TEMPERATE_END),
-            COLOR_TEMPERATE,
-            COLOR_PEAKS
-        )
+/// Swap a chunk's lakes and rivers for a new surface (or none).
+/// They ride along with the chunk and are despawned with it.
+fn replace_inland_water(world: &mut World, chunk: Entity, water: Option<Mesh>) {
+    let old_water: Vec<Entity> = world.get::<Children>(chunk)
+        .map(|children| children.iter().copied().filter(|child| world.get::<Water>(*child).is_some()).collect())
+        .unwrap_or_default();
+    for child in old_water {
+        world.entity_mut(child).despawn_recursive();
     }
-}
-
-fn terrain_color_gradient(ratio: f32, rgba1: [f32; 4], rgba2: [f32; 4]) -> [f32;4] {
-    let [r1, g1, b1, a1] = rgba1;
-    let [r2, g2, b2, a2] = rgba2;
 
-    [
-        r1 + (r2-r1)*(ratio),
-        g1 + (g2-g1)*(ratio),
-        b1 + (b2-b1)*(ratio),
-        a1 + (a2-a1)*(ratio)
-    ]
+    let Some(water_mesh) = water else { return };
+    let mesh = world.resource_mut::<Assets<Mesh>>().add(water_mesh);
+    let material = world.resource::<WaterMaterial>().0.clone();
+    world.entity_mut(chunk).with_children(|parent| {
+        parent.spawn(MaterialMeshBundle { mesh, material, ..default() }).insert(Water);
+    });
 }
 
-/// Spawn a terrain chunk at the given chunk coordinates
-fn spawn_terrain_chunk(
-    commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    chunk_x: i32,
-    chunk_z: i32,
-    subdivisions: u32,
-    with_collider: bool,
-) -> Entity {
-    let (world_x, world_z) = chunk_to_world(chunk_x, chunk_z);
-    let mesh = generate_terrain_mesh(world_x, world_z, CHUNK_SIZE, subdivisions);
-
-    let terrain_material = StandardMaterial {
-        alpha_mode: AlphaMode::Opaque,
-        double_sided: true,
-        perceptual_roughness: 1.0,
-        reflectance: 0.4,
-        cull_mode: Some(Face::Back),
-        flip_normal_map_y: true,
-        ..default()
-    };
+/// Add colliders to chunks the player has come close to, and drop them from chunks they've left.
+/// Colliders don't depend on render LOD, only on distance.
+fn update_terrain_colliders(
+    mut commands: Commands,
+    sampler: Res<TerrainSampler>,
+    terrain_chunks: Query<(Entity, &Terrain, Has<TerrainCollider>, Has<GenColliderTask>)>,
+    player: Query<&Transform, With<player::Player>>,
+) {
+    let Ok(player_trans) = player.get_single() else { return };
+    let player_key = world_to_chunk(player_trans.translation.x, player_trans.translation.z);
+
+    for (entity, terrain, has_collider, collider_pending) in terrain_chunks.iter() {
+        let needs_collider = tile_distance((terrain.chunk_x, terrain.chunk_z), player_key) <= COLLIDER_DISTANCE;
+        if needs_collider && !has_collider && !collider_pending {
+            spawn_collider_task(&mut commands, &sampler, entity, terrain.chunk_x, terrain.chunk_z);
+        } else if !needs_collider && (has_collider || collider_pending) {
+            // Dropping an in-flight GenColliderTask cancels it
+            commands.entity(entity)
+                .remove::<Collider>()
+                .remove::<TerrainCollider>()
+                .remove::<GenColliderTask>();
+        }
+    }
+}
 
-    let mut entity_commands = commands.spawn(PbrBundle {
-        mesh: meshes.add(mesh.clone()),
-        material: materials.add(terrain_material),
-        transform: Transform::from_xyz(world_x, 0., world_z),
-        ..default()
-    });
+/// Rebuild the colliders of chunks whose ground was edited. The old collider stays until the new one is ready.
+fn refresh_edited_colliders(
+    mut commands: Commands,
+    mut edits: EventReader<TerrainEdited>,
+    sampler: Res<TerrainSampler>,
+    terrain_chunks: Query<(Entity, &Terrain), With<TerrainCollider>>,
+) {
+    for edit in edits.read() {
+        let (min_key, max_key) = (world_to_chunk(edit.min.x, edit.min.y), world_to_chunk(edit.max.x, edit.max.y));
+        for (entity, terrain) in terrain_chunks.iter() {
+            if (min_key.0..=max_key.0).contains(&terrain.chunk_x) && (min_key.1..=max_key.1).contains(&terrain.chunk_z) {
+                spawn_collider_task(&mut commands, &sampler, entity, terrain.chunk_x, terrain.chunk_z);
+            }
+        }
+    }
+}
 
-    entity_commands
-        .insert(Terrain {
-            chunk_x,
-            chunk_z,
-            lod_level: subdivisions,
-        })
-        .insert(terrain_chunk_aabb());
-
-    if with_collider {
-        let collider_shape = ComputedColliderShape::TriMesh;
-        if let Some(collider) = Collider::from_bevy_mesh(&mesh, &collider_shape) {
-            entity_commands.insert(collider);
-            entity_commands.insert(TerrainCollider);
+/// Build a heightfield collider for a chunk straight from sampled heights.
+/// Uses a fixed resolution so colliders never need rebuilding when the render LOD changes.
+fn generate_terrain_collider(sampler: &dyn HeightSampler, center_x: f32, center_z: f32, size: f32) -> Collider {
+    let samples = COLLIDER_SUBDIVISIONS + 1;
+    let half_size = size / 2.0;
+    let segment_size = size / COLLIDER_SUBDIVISIONS as f32;
+
+    // Rapier heightfields are column-major with rows along z and columns along x,
+    // centered on the collider's origin (the chunk center)
+    let mut heights: Vec<f32> = Vec::with_capacity(samples * samples);
+    for col in 0..samples {
+        let world_x = center_x - half_size + col as f32 * segment_size;
+        for row in 0..samples {
+            let world_z = center_z - half_size + row as f32 * segment_size;
+            heights.push(sampler.height(world_x, world_z));
         }
     }
 
-    entity_commands.id()
+    Collider::heightfield(heights, samples, samples, Vec3::new(size, 1.0, size))
 }
 
-/// Spawn a terrain chunk asynchronously to avoid blocking the main thread
-fn spawn_terrain_chunk_async(
-    commands: &mut Commands,
-    chunk_x: i32,
-    chunk_z: i32,
-    subdivisions: u32,
-    with_collider: bool,
-) {
+/// Start building a collider for an already spawned chunk
+fn spawn_collider_task(commands: &mut Commands, sampler: &TerrainSampler, entity: Entity, chunk_x: i32, chunk_z: i32) {
     let thread_pool = AsyncComputeTaskPool::get();
     let (world_x, world_z) = chunk_to_world(chunk_x, chunk_z);
-    let transform = Transform::from_xyz(world_x, 0., world_z);
-    let aabb = terrain_chunk_aabb();
-
-    let task_entity = commands.spawn_empty().id();
-
+    let sampler = sampler.clone();
     let task = thread_pool.spawn(async move {
-        let mut command_queue = CommandQueue::default();
-
-        // Generate mesh on background thread
-        let mesh = generate_terrain_mesh(world_x, world_z, CHUNK_SIZE, subdivisions);
-        let mesh_for_collider = if with_collider { Some(mesh.clone()) } else { None };
-
-        command_queue.push(move |world: &mut World| {
-            let (mesh_handle, material_handle) = {
-                let mut system_state = SystemState::<(
-                    ResMut<Assets<Mesh>>,
-                    ResMut<Assets<StandardMaterial>>,
-                )>::new(world);
-                let (mut meshes, mut materials) = system_state.get_mut(world);
-
-                let terrain_material = StandardMaterial {
-                    alpha_mode: AlphaMode::Opaque,
-                    double_sided: true,
-                    perceptual_roughness: 1.0,
-                    reflectance: 0.4,
-                    cull_mode: Some(Face::Back),
-                    flip_normal_map_y: true,
-                    ..default()
-                };
-
-                (meshes.add(mesh), materials.add(terrain_material))
-            };
-
-            let mut entity = world.entity_mut(task_entity);
-            entity
-                .insert(PbrBundle {
-                    mesh: mesh_handle,
-                    material: material_handle,
-                    transform,
-                    ..default()
-                })
-                .insert(Terrain {
-                    chunk_x,
-                    chunk_z,
-                    lod_level: subdivisions,
-                })
-                .insert(aabb)
-                .remove::<GenTerrainTask>();
-
-            // Add collider if needed
-            if let Some(collider_mesh) = mesh_for_collider {
-                let collider_shape = ComputedColliderShape::TriMesh;
-                if let Some(collider) = Collider::from_bevy_mesh(&collider_mesh, &collider_shape) {
-                    entity.insert(collider);
-                    entity.insert(TerrainCollider);
-                }
-            }
-        });
-
-        command_queue
+        generate_terrain_collider(&sampler, world_x, world_z, CHUNK_SIZE)
     });
-
-    commands.entity(task_entity).insert(GenTerrainTask(task));
+    commands.entity(entity).insert(GenColliderTask(task));
 }
 
-/// Handle completed async terrain generation tasks
-fn handle_terrain_tasks(mut commands: Commands, mut terrain_tasks: Query<&mut GenTerrainTask>) {
-    for mut task in &mut terrain_tasks {
-        if let Some(mut commands_queue) = block_on(poll_once(&mut task.0)) {
-            commands.append(&mut commands_queue);
+/// Handle completed async collider generation tasks
+fn handle_collider_tasks(mut commands: Commands, mut collider_tasks: Query<(Entity, &mut GenColliderTask)>) {
+    for (entity, mut task) in &mut collider_tasks {
+        if let Some(collider) = block_on(poll_once(&mut task.0)) {
+            commands.entity(entity)
+                .insert(collider)
+                .insert(TerrainCollider)
+                .remove::<GenColliderTask>();
         }
     }
 }
 
+/// Any water surface (the ocean or a chunk's lakes and rivers)
 #[derive(Component)]
 struct Water;
 
+/// The sea level plane, which follows the player
+#[derive(Component)]
+struct Ocean;
+
+/// Material shared by every water surface
+#[derive(Resource)]
+struct WaterMaterial(Handle<ExtendedMaterial<StandardMaterial, WaterMaterialExtension>>);
+
+/// Scrolls the water texture in world space (see water_shader.wgsl), so every surface lines up
+/// and the ocean can snap along with the player without the texture jumping
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct WaterMaterialExtension {}
+
+impl MaterialExtension for WaterMaterialExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/water_shader.wgsl".into()
+    }
+}
+
 /// Generate a simple plane mesh (used for water)
 fn generate_simple_plane(size: f32, subdivisions: u32) -> Mesh {
     let asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
@@ -578,26 +427,14 @@ fn generate_simple_plane(size: f32, subdivisions: u32) -> Mesh {
     mesh
 }
 
-fn spawn_water_plane(
-    commands: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    asset_server: &Res<AssetServer>,
+/// Create the shared water material and the ocean plane, which update_ocean keeps centered on the player
+fn setup_water(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, WaterMaterialExtension>>>,
+    asset_server: Res<AssetServer>,
+    config: Res<WorldGenConfig>,
 ) {
-    let water_size = CHUNK_SIZE * (CHUNKS_RADIUS as f32 * 2.0 + 1.0);
-    let mut water_mesh = generate_simple_plane(water_size, 1);
-
-    let pos_attr = water_mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
-    let VertexAttributeValues::Float32x3(pos_attr) = pos_attr else {
-        panic!("Unexpected vertex format, expected Float32x3");
-    };
-
-    let water_uvs: Vec<[f32; 2]> = pos_attr.iter().map(|[x,_y,z]| { [x / WATER_TEXTURE_SCALE, z / WATER_TEXTURE_SCALE]}).collect();
-
-    water_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, water_uvs);
-
-    let _ = water_mesh.generate_tangents();
-
     let sampler_desc = ImageSamplerDescriptor {
         address_mode_u: ImageAddressMode::Repeat,
         address_mode_v: ImageAddressMode::Repeat,
@@ -608,32 +445,101 @@ fn spawn_water_plane(
     };
     let normal_handle = asset_server.load_with_settings("water_normal.png", settings);
 
-    commands.spawn(PbrBundle {
+    let water_material = materials.add(ExtendedMaterial { base: StandardMaterial {
+        base_color: Color::rgba(0.,54./256.,78./256., 236./256.),
+        perceptual_roughness: 0.7,
+        metallic: 0.2,
+        reflectance: 0.45,
+        diffuse_transmission: 0.0,
+        specular_transmission:0.3,
+        normal_map_texture: Some(normal_handle.clone()),
+        flip_normal_map_y: true,
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    }, extension: WaterMaterialExtension {} });
+
+    // The ocean covers the whole chunk grid around the player
+    let water_size = CHUNK_SIZE * (CHUNKS_RADIUS as f32 * 2.0 + 1.0);
+    let mut water_mesh = generate_simple_plane(water_size, 1);
+    let _ = water_mesh.generate_tangents();
+    commands.spawn(MaterialMeshBundle {
         mesh: meshes.add(water_mesh),
-        material: materials.add(StandardMaterial {
-            base_color: Color::rgba(0.,54./256.,78./256., 236./256.),
-            perceptual_roughness: 0.7,
-            metallic: 0.2,
-            reflectance: 0.45,
-            diffuse_transmission: 0.0,
-            specular_transmission:0.3,
-            normal_map_texture: Some(normal_handle.clone()),
-            flip_normal_map_y: true,
-            alpha_mode: AlphaMode::Blend,
-            ..default()
-        }),
-        transform: Transform::from_xyz(0.0, WATER_LEVEL, 0.0),
+        material: water_material.clone(),
+        transform: Transform::from_xyz(0.0, config.water_level, 0.0),
         ..default()
-    }).insert(Water);
+    }).insert(Water).insert(Ocean);
+
+    commands.insert_resource(WaterMaterial(water_material));
+}
+
+/// Lake and river surface for one chunk (centered at local origin), or None if the chunk has no inland water.
+/// Only cells touching water above sea level get triangles; the ocean plane covers everything at sea level.
+fn generate_inland_water_mesh(sampler: &dyn HeightSampler, center_x: f32, center_z: f32, size: f32) -> Option<Mesh> {
+    let sea_level = sampler.config().water_level;
+    let half_size = size / 2.0;
+    let segment_size = size / WATER_SUBDIVISIONS as f32;
+    let row_size = (WATER_SUBDIVISIONS + 1) as usize;
+
+    let mut positions: Vec<[f32; 3]> = Vec::with_capacity(row_size * row_size);
+    let mut wet: Vec<bool> = Vec::with_capacity(row_size * row_size);
+    for z in 0..row_size {
+        for x in 0..row_size {
+            let local_x = -half_size + x as f32 * segment_size;
+            let local_z = -half_size + z as f32 * segment_size;
+            let level = sampler.water_level(center_x + local_x, center_z + local_z);
+            let height = sampler.height(center_x + local_x, center_z + local_z);
+            positions.push([local_x, level, local_z]);
+            wet.push(level > sea_level + INLAND_WATER_MIN_HEIGHT && level > height);
+        }
+    }
+
+    let mut indices: Vec<u32> = Vec::new();
+    for z in 0..WATER_SUBDIVISIONS as usize {
+        for x in 0..WATER_SUBDIVISIONS as usize {
+            let i = z * row_size + x;
+            if !(wet[i] || wet[i + 1] || wet[i + row_size] || wet[i + row_size + 1]) {
+                continue;
+            }
+            let i = i as u32;
+            let row = row_size as u32;
+            indices.extend_from_slice(&[i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+        }
+    }
+    if indices.is_empty() {
+        return None;
+    }
+
+    let uvs: Vec<[f32; 2]> = positions.iter()
+        .map(|[x, _y, z]| [(center_x + x) / WATER_TEXTURE_SCALE, (center_z + z) / WATER_TEXTURE_SCALE])
+        .collect();
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+
+    let asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, asset_usage);
+    mesh.insert_indices(Indices::U32(indices));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    let _ = mesh.generate_tangents();
+
+    Some(mesh)
 }
 
 /// Generate a terrain mesh with custom mesh generation (replaces deprecated Plane)
-/// Creates a subdivided plane with height sampling from Perlin noise
-fn generate_terrain_mesh(center_x: f32, center_z: f32, size: f32, subdivisions: u32) -> Mesh {
+/// Creates a subdivided plane with height sampling from the world's HeightSampler.
+/// Edges bordering a coarser neighbour are stitched to the neighbour's edge (see stitch_edge)
+pub fn generate_terrain_mesh(
+    config: &WorldGenConfig,
+    sampler: &dyn HeightSampler,
+    center_x: f32,
+    center_z: f32,
+    size: f32,
+    subdivisions: u32,
+    edge_lods: NeighbourLods,
+) -> Mesh {
     let asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, asset_usage);
 
-    let height_map = perlin::terrain_perlin();
     let half_size = size / 2.0;
     let segment_size = size / subdivisions as f32;
     let vertex_count = ((subdivisions + 1) * (subdivisions + 1)) as usize;
@@ -643,7 +549,7 @@ fn generate_terrain_mesh(center_x: f32, center_z: f32, size: f32, subdivisions:
     let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(vertex_count);
     let mut vertex_colors: Vec<[f32; 4]> = Vec::with_capacity(vertex_count);
 
-    // Generate vertices with height from Perlin noise
+    // Generate vertices with height from the sampler
     for z in 0..=subdivisions {
         for x in 0..=subdivisions {
             // Local position relative to chunk center
@@ -654,15 +560,22 @@ fn generate_terrain_mesh(center_x: f32, center_z: f32, size: f32, subdivisions:
             let world_x = center_x + local_x;
             let world_z = center_z + local_z;
 
-            let height = sample_terrain_height(&height_map, world_x, world_z);
+            let height = sampler.height(world_x, world_z);
 
             positions.push([local_x, height, local_z]);
             normals.push([0.0, 1.0, 0.0]); // Will be recalculated below
             uvs.push([local_x / (TILE_WIDTH as f32 * TEXTURE_SCALE), local_z / (TILE_WIDTH as f32 * TEXTURE_SCALE)]);
-            vertex_colors.push(get_terrain_color(height));
         }
     }
 
+    // Stitch edges to coarser neighbours so edge vertices lie on the neighbour's edge
+    let row_size = (subdivisions + 1) as usize;
+    let last = subdivisions as usize;
+    stitch_edge(&mut positions, subdivisions, edge_lods.neg_x, |i| i * row_size);
+    stitch_edge(&mut positions, subdivisions, edge_lods.pos_x, |i| i * row_size + last);
+    stitch_edge(&mut positions, subdivisions, edge_lods.neg_z, |i| i);
+    stitch_edge(&mut positions, subdivisions, edge_lods.pos_z, |i| last * row_size + i);
+
     // Generate indices
     let mut indices: Vec<u32> = Vec::with_capacity((subdivisions * subdivisions * 6) as usize);
     for z in 0..subdivisions {
@@ -680,23 +593,30 @@ fn generate_terrain_mesh(center_x: f32, center_z: f32, size: f32, subdivisions:
     }
 
     // Calculate proper normals based on terrain geometry
-    let row_size = (subdivisions + 1) as usize;
     for z in 0..=subdivisions as usize {
         for x in 0..=subdivisions as usize {
             let idx = z * row_size + x;
             let pos = positions[idx];
+            let world_x = center_x + pos[0];
+            let world_z = center_z + pos[2];
 
-            // Get neighboring heights for normal calculation
-            let left = if x > 0 { positions[idx - 1][1] } else { pos[1] };
-            let right = if x < subdivisions as usize { positions[idx + 1][1] } else { pos[1] };
-            let up = if z > 0 { positions[idx - row_size][1] } else { pos[1] };
-            let down = if z < subdivisions as usize { positions[idx + row_size][1] } else { pos[1] };
+            // Get neighboring heights for normal calculation.
+            // Edge vertices sample across the border so normals match the neighbouring chunk.
+            let left = if x > 0 { positions[idx - 1][1] } else { sampler.height(world_x - segment_size, world_z) };
+            let right = if x < last { positions[idx + 1][1] } else { sampler.height(world_x + segment_size, world_z) };
+            let up = if z > 0 { positions[idx - row_size][1] } else { sampler.height(world_x, world_z - segment_size) };
+            let down = if z < last { positions[idx + row_size][1] } else { sampler.height(world_x, world_z + segment_size) };
 
             // Calculate normal from height differences
             let dx = (right - left) / (2.0 * segment_size);
             let dz = (down - up) / (2.0 * segment_size);
             let normal = Vec3::new(-dx, 1.0, -dz).normalize();
             normals[idx] = normal.to_array();
+
+            // Colour by biome, using the slope we just worked out
+            let climate = sampler.climate(world_x, world_z);
+            let slope = normal.y.clamp(-1.0, 1.0).acos();
+            vertex_colors.push(biome::terrain_color(config, climate, pos[1], slope));
         }
     }
 
@@ -711,24 +631,41 @@ fn generate_terrain_mesh(center_x: f32, center_z: f32, size: f32, subdivisions:
     mesh
 }
 
-fn update_water(
-    _commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    _materials: ResMut<Assets<StandardMaterial>>,
-    _asset_server: Res<AssetServer>,
-    mut water: Query<(Entity,&Handle<Mesh>), With<Water> >,
+/// Snap the vertices along one chunk edge onto a coarser neighbour's edge.
+/// Vertices shared with the neighbour are left alone; the ones in between are linearly
+/// interpolated between them, which is exactly the line the neighbour's triangles follow.
+/// `edge_index` maps a position along the edge (0..=subdivisions) to a vertex index.
+fn stitch_edge(positions: &mut [[f32; 3]], subdivisions: u32, neighbour_subdivisions: u32, edge_index: impl Fn(usize) -> usize) {
+    if neighbour_subdivisions >= subdivisions {
+        // Neighbour is as fine or finer - it stitches to us instead
+        return;
+    }
+    let step = (subdivisions / neighbour_subdivisions) as usize;
+    for i in 0..=subdivisions as usize {
+        let offset = i % step;
+        if offset == 0 {
+            continue;
+        }
+        let start = i - offset;
+        let end = start + step;
+        let t = offset as f32 / step as f32;
+        let start_height = positions[edge_index(start)][1];
+        let end_height = positions[edge_index(end)][1];
+        positions[edge_index(i)][1] = start_height + (end_height - start_height) * t;
+    }
+}
+
+/// Keep the ocean centered on the player's chunk (the shader scrolls the texture)
+fn update_ocean(
+    mut ocean: Query<&mut Transform, With<Ocean>>,
+    player: Query<&Transform, (With<player::Player>, Without<Ocean>)>,
 ) {
-    let Ok((_water_ent, water_mesh_handle)) = water.get_single_mut() else {
-        return
-    };
-    let water_mesh = meshes.get_mut(water_mesh_handle).unwrap();
-    let water_uvs = water_mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0).unwrap();
-    let VertexAttributeValues::Float32x2(uv_attr) = water_uvs else {
-        panic!("Unexpected vertex format, expected Float32x3");
-    };
-    for [x,y] in uv_attr.iter_mut() {
-        *x += WATER_SCROLL_SPEED;
-        *y += WATER_SCROLL_SPEED;
+    let Ok(player_trans) = player.get_single() else { return };
+    let (player_chunk_x, player_chunk_z) = world_to_chunk(player_trans.translation.x, player_trans.translation.z);
+    let (ocean_x, ocean_z) = chunk_to_world(player_chunk_x, player_chunk_z);
+    for mut transform in &mut ocean {
+        transform.translation.x = ocean_x;
+        transform.translation.z = ocean_z;
     }
 }
 
@@ -736,6 +673,76 @@ pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (update_terrain, handle_terrain_tasks, update_water));
+        app.add_plugins(StreamingPlugin::<TerrainLayer>::default())
+            .add_plugins(MaterialPlugin::<ExtendedMaterial<StandardMaterial, WaterMaterialExtension>>::default())
+            .add_systems(Startup, setup_water)
+            .add_systems(Update, (update_terrain_colliders, refresh_edited_colliders, handle_collider_tasks, update_ocean));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    /// Mesh for a chunk at the LOD it gets with the player in chunk (0, 0)
+    fn chunk_mesh(config: &WorldGenConfig, sampler: &TerrainSampler, chunk_x: i32, chunk_z: i32) -> (u32, Vec<[f32; 3]>) {
+        let subdivisions = get_lod_level(chunk_x.abs().max(chunk_z.abs()));
+        let edge_lods = get_neighbour_lods(chunk_x, chunk_z, 0, 0);
+        let (world_x, world_z) = chunk_to_world(chunk_x, chunk_z);
+        let mesh = generate_terrain_mesh(config, sampler, world_x, world_z, CHUNK_SIZE, subdivisions, edge_lods);
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            panic!("Unexpected vertex format, expected Float32x3");
+        };
+        (subdivisions, positions.clone())
+    }
+
+    /// (position along the edge, height) of a mesh's vertices on one edge, in order.
+    /// `axis` is 0 for an edge of constant x, 2 for constant z; `side` is -1 or 1.
+    fn edge(positions: &[[f32; 3]], axis: usize, side: f32) -> Vec<(f32, f32)> {
+        let along = 2 - axis;
+        let mut edge: Vec<(f32, f32)> = positions.iter()
+            .filter(|p| p[axis] == side * CHUNK_SIZE / 2.0)
+            .map(|p| (p[along], p[1]))
+            .collect();
+        edge.sort_by(|a, b| a.0.total_cmp(&b.0));
+        edge
+    }
+
+    /// Height of the coarse edge's line segments at `t`
+    fn coarse_height_at(coarse: &[(f32, f32)], t: f32) -> f32 {
+        let i = coarse.windows(2).position(|w| t >= w[0].0 && t <= w[1].0).expect("edge vertex outside the neighbouring edge");
+        let ((t0, h0), (t1, h1)) = (coarse[i], coarse[i + 1]);
+        h0 + (h1 - h0) * (t - t0) / (t1 - t0)
+    }
+
+    /// Every vertex on the finer chunk's shared edge has to lie on the coarser chunk's edge, or there's a crack
+    fn assert_seamless(fine: (i32, i32), coarse: (i32, i32)) {
+        let config = WorldGenConfig::default();
+        let sampler = TerrainSampler::from_config(&config);
+        let (fine_subdivisions, fine_positions) = chunk_mesh(&config, &sampler, fine.0, fine.1);
+        let (coarse_subdivisions, coarse_positions) = chunk_mesh(&config, &sampler, coarse.0, coarse.1);
+        assert!(fine_subdivisions > coarse_subdivisions, "{:?} should be finer than {:?}", fine, coarse);
+
+        let (axis, side) = if fine.0 != coarse.0 { (0, (coarse.0 - fine.0) as f32) } else { (2, (coarse.1 - fine.1) as f32) };
+        let fine_edge = edge(&fine_positions, axis, side);
+        let coarse_edge = edge(&coarse_positions, axis, -side);
+        assert_eq!(fine_edge.len(), fine_subdivisions as usize + 1);
+        assert_eq!(coarse_edge.len(), coarse_subdivisions as usize + 1);
+        for (t, height) in fine_edge {
+            let expected = coarse_height_at(&coarse_edge, t);
+            assert!((height - expected).abs() < 1e-3, "crack between {:?} and {:?} at {}: {} vs {}", fine, coarse, t, height, expected);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn edges_match_coarser_neighbours() {
+        // Across each LOD ring, along both axes and on both sides of the player
+        for distance in [LOD_1_DISTANCE, LOD_2_DISTANCE, LOD_3_DISTANCE] {
+            assert_seamless((distance - 1, 0), (distance, 0));
+            assert_seamless((0, distance - 1), (0, distance));
+            assert_seamless((1 - distance, 1), (-distance, 1));
+            assert_seamless((-1, 1 - distance), (-1, -distance));
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy_rapier3d::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use crate::entities::player::Player;
+use crate::entities::tree::{TreeCollider, TreeId, TreeLayer};
+use crate::entities::tree_index::TreeIndex;
+use crate::util::streaming::StreamingGrid;
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
+
+// Chopping
+const CHOP_KEY: KeyCode = KeyCode::KeyF;
+const CHOP_REACH: f32 = 3.0; // from the player to the trunk's surface
+const CHOP_DAMAGE: f32 = 25.0;
+// Felled trees
+const FALL_SPEED: f32 = 0.5; // radians per second the trunk starts tipping over at
+const FELLED_TREE_LIFETIME: f32 = 30.0; // seconds before a fallen trunk is cleared away
+
+/// Hurt a tree. Once its health runs out it's felled: gone from the world for good (see WorldDelta),
+/// with a trunk falling away from `from`. Chopping sends one for the tree in reach, an explosion
+/// would send one for every tree TreeIndex::trees_near finds around it.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct DamageTree {
+    pub id: TreeId,
+    pub amount: f32,
+    /// Where the blow came from, e.g. the player or the middle of an explosion
+    pub from: Vec3,
+}
+
+/// A felled tree's trunk while it falls and lies on the ground
+#[derive(Component)]
+pub struct FelledTree {
+    timer: Timer,
+}
+
+/// Felled trees are lit like the standing ones but don't sway, so they don't need the tree shader
+#[derive(Resource)]
+struct FelledTreeMaterial(Handle<StandardMaterial>);
+
+fn setup_felled_tree_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    commands.insert_resource(FelledTreeMaterial(materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        perceptual_roughness: 1.0,
+        reflectance: 0.3,
+        cull_mode: None,
+        ..default()
+    })));
+}
+
+/// Chop the closest tree in reach
+fn chop_trees(
+    keys: Res<ButtonInput<KeyCode>>,
+    player: Query<&Transform, With<Player>>,
+    index: Option<Res<TreeIndex>>,
+    mut damage: EventWriter<DamageTree>,
+) {
+    if !keys.just_pressed(CHOP_KEY) {
+        return;
+    }
+    let (Ok(player_trans), Some(index)) = (player.get_single(), index) else { return };
+    let position = player_trans.translation;
+    let Some(tree) = index.nearest(position, CHOP_REACH * 2.0) else { return };
+    if tree.position.xz().distance(position.xz()) - tree.trunk_radius <= CHOP_REACH {
+        damage.send(DamageTree { id: tree.id, amount: CHOP_DAMAGE, from: position });
+    }
+}
+
+/// Record damage, and fell trees that have taken enough: remember they're gone, rebuild their tile
+/// without them and drop a falling trunk in their place
+#[allow(clippy::too_many_arguments)]
+fn damage_trees(
+    mut commands: Commands,
+    mut events: EventReader<DamageTree>,
+    index: Option<Res<TreeIndex>>,
+    deltas: Res<WorldDeltas>,
+    mut grid: Option<ResMut<StreamingGrid<TreeLayer>>>,
+    colliders: Query<(Entity, &TreeCollider)>,
+    config: Res<WorldGenConfig>,
+    material: Res<FelledTreeMaterial>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Some(index) = index else { return };
+    for event in events.read() {
+        // Already felled (e.g. by an earlier event this frame)
+        let Some(tree) = index.get(event.id) else { continue };
+        if tree.health > event.amount {
+            *deltas.write().tree_damage.entry(event.id).or_default() += event.amount;
+            continue;
+        }
+        let Some(placed) = index.placement(event.id) else { continue };
+        {
+            let mut deltas = deltas.write();
+            deltas.tree_damage.remove(&event.id);
+            deltas.removed_trees.insert(event.id);
+        }
+        if let Some(grid) = grid.as_mut() {
+            grid.regenerate(event.id.tile);
+        }
+        // The tile keeps its colliders until the rebuild lands, the standing trunk's has to go now or the falling one spawns inside it
+        for (entity, collider) in &colliders {
+            if collider.0 == event.id {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        // The same tree the tile drew, as one rigid body standing where it stood
+        let geometry = config.trees.get(placed.species).generate(&mut StdRng::seed_from_u64(placed.seed), Vec3::ZERO, placed.scale);
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
+        mesh.insert_indices(Indices::U32(geometry.indices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; geometry.verts.len()]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, geometry.verts.iter().map(|v| v.to_array()).collect::<Vec<_>>());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, geometry.colors);
+
+        // Tip over away from the blow
+        let away = Vec3::new(tree.position.x - event.from.x, 0.0, tree.position.z - event.from.z).try_normalize().unwrap_or(Vec3::X);
+        let radius = tree.trunk_radius;
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(mesh),
+            material: material.0.clone(),
+            transform: Transform::from_translation(tree.position),
+            ..default()
+        })
+        .insert(RigidBody::Dynamic)
+        .insert(Collider::capsule(Vec3::Y * radius, Vec3::Y * (tree.height - radius).max(radius), radius))
+        .insert(Velocity { linvel: Vec3::ZERO, angvel: Vec3::Y.cross(away) * FALL_SPEED })
+        .insert(FelledTree { timer: Timer::from_seconds(FELLED_TREE_LIFETIME, TimerMode::Once) })
+        .insert(Name::new("FelledTree"));
+    }
+}
+
+fn clear_felled_trees(mut commands: Commands, mut trees: Query<(Entity, &mut FelledTree)>, time: Res<Time>) {
+    for (entity, mut tree) in &mut trees {
+        if tree.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub struct TreeFellingPlugin;
+
+impl Plugin for TreeFellingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageTree>()
+            .add_systems(Startup, setup_felled_tree_material)
+            .add_systems(Update, (chop_trees, damage_trees, clear_felled_trees).chain());
+    }
+}
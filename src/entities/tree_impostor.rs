@@ -0,0 +1,378 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, MeshVertexAttribute, MeshVertexBufferLayout};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{AsBindGroup, PrimitiveTopology, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, VertexFormat};
+use bevy::render::texture::ImageLoaderSettings;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use futures_lite::future::poll_once;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use crate::entities::tree_species::{TreeGeometry, TreeSpecies};
+use crate::util::cli::BakeImpostorsArgs;
+use crate::util::worldgen::WorldGenConfig;
+
+// Atlas layout: a block of IMPOSTOR_FRAMES x IMPOSTOR_FRAMES views per species, side by side in TreeSpecies::ALL order.
+// The views cover the upper hemisphere with a hemi-octahedral mapping. Keep in sync with tree_impostor.wgsl
+const IMPOSTOR_FRAMES: u32 = 8;
+const IMPOSTOR_FRAME_SIZE: u32 = 64; // pixels
+pub const IMPOSTOR_DIRECTORY: &str = "assets/impostors";
+const ALBEDO_FILE: &str = "trees_albedo.png";
+const NORMAL_FILE: &str = "trees_normal.png";
+// Hash of the tree shapes the atlases were baked from, so startup can tell when they're out of date
+const HASH_FILE: &str = "trees.hash";
+// Every impostor of a species is a picture of this one tree
+const IMPOSTOR_SEED: u64 = 1;
+
+const ATTRIBUTE_IMPOSTOR: MeshVertexAttribute = MeshVertexAttribute::new("Impostor", 988540918, VertexFormat::Float32x2);
+
+/// The sphere a species' impostor covers, relative to the tree's base at scale 1.
+/// Centered over the trunk so impostors line up with the detailed trees they replace.
+#[derive(Clone, Copy, Debug)]
+pub struct ImpostorBounds {
+    pub center_y: f32,
+    pub radius: f32,
+}
+
+impl ImpostorBounds {
+    fn of(geometry: &TreeGeometry) -> Self {
+        let (min_y, max_y) = geometry.verts.iter().fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v.y), max.max(v.y)));
+        let center_y = if geometry.verts.is_empty() { 0.0 } else { (min_y + max_y) / 2.0 };
+        let center = Vec3::new(0.0, center_y, 0.0);
+        let radius = geometry.verts.iter().map(|v| v.distance(center)).fold(0.1, f32::max);
+        Self { center_y, radius }
+    }
+}
+
+/// The tree a species' impostor is baked from
+fn impostor_tree(config: &WorldGenConfig, species: TreeSpecies) -> TreeGeometry {
+    config.trees.get(species).generate(&mut StdRng::seed_from_u64(IMPOSTOR_SEED), Vec3::ZERO, 1.0)
+}
+
+/// Bounds of every species' impostor, indexed by TreeSpecies::index
+pub fn impostor_bounds(config: &WorldGenConfig) -> Vec<ImpostorBounds> {
+    TreeSpecies::ALL.iter().map(|species| ImpostorBounds::of(&impostor_tree(config, *species))).collect()
+}
+
+/// 0..1 on both axes to a direction on the upper hemisphere, the inverse of hemi_octahedral_encode in tree_impostor.wgsl
+fn hemi_octahedral_decode(uv: Vec2) -> Vec3 {
+    let e = uv * 2.0 - 1.0;
+    let p = Vec2::new(e.x + e.y, e.x - e.y) * 0.5;
+    Vec3::new(p.x, 1.0 - p.x.abs() - p.y.abs(), p.y).normalize()
+}
+
+/// Right and up on the picture of a view looking back along `direction`. Same as impostor_basis in tree_impostor.wgsl
+fn impostor_basis(direction: Vec3) -> (Vec3, Vec3) {
+    let right = if direction.y.abs() > 0.999 { Vec3::X } else { Vec3::Y.cross(direction).normalize() };
+    (right, direction.cross(right))
+}
+
+/// One view of a tree: linear colour and coverage, and the normal of whatever's in front
+struct Frame {
+    color: Vec<[f32; 4]>,
+    normal: Vec<Vec3>,
+    depth: Vec<f32>,
+}
+
+/// Draw the tree as seen from `direction` (pointing from the tree to the camera), orthographic and fitted to its bounds.
+/// Foliage is double sided, so normals are turned to face the camera.
+fn rasterise_view(geometry: &TreeGeometry, bounds: &ImpostorBounds, direction: Vec3) -> Frame {
+    let size = IMPOSTOR_FRAME_SIZE as usize;
+    let mut frame = Frame {
+        color: vec![[0.0; 4]; size * size],
+        normal: vec![Vec3::Y; size * size],
+        depth: vec![f32::MIN; size * size],
+    };
+    let (right, up) = impostor_basis(direction);
+    let center = Vec3::new(0.0, bounds.center_y, 0.0);
+    // x and y in pixels (y down), z towards the camera
+    let project = |p: Vec3| {
+        let d = p - center;
+        Vec3::new(
+            (d.dot(right) / bounds.radius * 0.5 + 0.5) * size as f32,
+            (0.5 - d.dot(up) / bounds.radius * 0.5) * size as f32,
+            d.dot(direction),
+        )
+    };
+    let edge = |a: Vec3, b: Vec3, x: f32, y: f32| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+
+    for triangle in geometry.indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+        let (pa, pb, pc) = (project(geometry.verts[a]), project(geometry.verts[b]), project(geometry.verts[c]));
+        let area = edge(pa, pb, pc.x, pc.y);
+        if area.abs() < 1e-6 {
+            continue;
+        }
+        let mut normal = (geometry.verts[b] - geometry.verts[a]).cross(geometry.verts[c] - geometry.verts[a]).normalize_or_zero();
+        if normal.dot(direction) < 0.0 {
+            normal = -normal;
+        }
+
+        let min_x = pa.x.min(pb.x).min(pc.x).floor().max(0.0) as usize;
+        let max_x = (pa.x.max(pb.x).max(pc.x).ceil() as usize).min(size);
+        let min_y = pa.y.min(pb.y).min(pc.y).floor().max(0.0) as usize;
+        let max_y = (pa.y.max(pb.y).max(pc.y).ceil() as usize).min(size);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let wa = edge(pb, pc, px, py) / area;
+                let wb = edge(pc, pa, px, py) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let depth = wa * pa.z + wb * pb.z + wc * pc.z;
+                let i = y * size + x;
+                if depth <= frame.depth[i] {
+                    continue;
+                }
+                frame.depth[i] = depth;
+                frame.normal[i] = normal;
+                let [ca, cb, cc] = [geometry.colors[a], geometry.colors[b], geometry.colors[c]];
+                frame.color[i] = [
+                    wa * ca[0] + wb * cb[0] + wc * cc[0],
+                    wa * ca[1] + wb * cb[1] + wc * cc[1],
+                    wa * ca[2] + wb * cb[2] + wc * cc[2],
+                    1.0,
+                ];
+            }
+        }
+    }
+
+    // Bleed colour and normal one pixel into the empty space, so filtering at the silhouette doesn't pull in black
+    let covered: Vec<bool> = frame.color.iter().map(|c| c[3] > 0.0).collect();
+    for y in 0..size {
+        for x in 0..size {
+            let i = y * size + x;
+            if covered[i] {
+                continue;
+            }
+            let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+            if let Some(n) = neighbours.iter().filter(|(nx, ny)| *nx < size && *ny < size).map(|(nx, ny)| ny * size + nx).find(|n| covered[*n]) {
+                let [r, g, b, _] = frame.color[n];
+                frame.color[i] = [r, g, b, 0.0];
+                frame.normal[i] = frame.normal[n];
+            }
+        }
+    }
+    frame
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn write_rgba_png(path: &Path, width: u32, height: u32, data: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(data).map_err(|e| e.to_string())
+}
+
+/// FNV-1a of the tree shapes as RON. Stable between builds, unlike std's hasher.
+fn trees_hash(config: &WorldGenConfig) -> u64 {
+    let trees = ron::to_string(&config.trees).unwrap_or_default();
+    trees.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Whether `directory` has atlases baked from the config's tree shapes
+fn impostors_up_to_date(config: &WorldGenConfig, directory: &Path) -> bool {
+    let baked = fs::read_to_string(directory.join(HASH_FILE)).ok().and_then(|hash| hash.trim().parse::<u64>().ok());
+    directory.join(ALBEDO_FILE).exists() && directory.join(NORMAL_FILE).exists() && baked == Some(trees_hash(config))
+}
+
+/// Bake the atlases from the command line, without starting the game
+pub fn run_bake(config: &WorldGenConfig, args: &BakeImpostorsArgs) -> Result<(), String> {
+    let directory = args.output.clone().unwrap_or_else(|| IMPOSTOR_DIRECTORY.to_string());
+    bake_impostors(config, Path::new(&directory))?;
+    println!("Baked impostors for {} tree species to {}", TreeSpecies::ALL.len(), directory);
+    Ok(())
+}
+
+/// Photograph every species' impostor tree from each view into the albedo (sRGB colour, alpha coverage)
+/// and normal (world space, linear) atlases, and note which tree shapes they show
+fn bake_impostors(config: &WorldGenConfig, directory: &Path) -> Result<(), String> {
+    fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    let frame_size = IMPOSTOR_FRAME_SIZE as usize;
+    let width = frame_size * IMPOSTOR_FRAMES as usize * TreeSpecies::ALL.len();
+    let height = frame_size * IMPOSTOR_FRAMES as usize;
+    let mut albedo = vec![0u8; width * height * 4];
+    let mut normals = vec![0u8; width * height * 4];
+
+    for species in TreeSpecies::ALL {
+        let geometry = impostor_tree(config, species);
+        let bounds = ImpostorBounds::of(&geometry);
+        for j in 0..IMPOSTOR_FRAMES {
+            for i in 0..IMPOSTOR_FRAMES {
+                let direction = hemi_octahedral_decode(Vec2::new(i as f32, j as f32) / (IMPOSTOR_FRAMES - 1) as f32);
+                let frame = rasterise_view(&geometry, &bounds, direction);
+                let origin_x = (species.index() * IMPOSTOR_FRAMES as usize + i as usize) * frame_size;
+                let origin_y = j as usize * frame_size;
+                for y in 0..frame_size {
+                    for x in 0..frame_size {
+                        let src = y * frame_size + x;
+                        let dst = ((origin_y + y) * width + origin_x + x) * 4;
+                        let [r, g, b, a] = frame.color[src];
+                        albedo[dst..dst + 4].copy_from_slice(&[
+                            to_byte(linear_to_srgb(r)), to_byte(linear_to_srgb(g)), to_byte(linear_to_srgb(b)), to_byte(a),
+                        ]);
+                        let n = frame.normal[src] * 0.5 + 0.5;
+                        normals[dst..dst + 4].copy_from_slice(&[to_byte(n.x), to_byte(n.y), to_byte(n.z), to_byte(a)]);
+                    }
+                }
+            }
+        }
+    }
+
+    write_rgba_png(&directory.join(ALBEDO_FILE), width as u32, height as u32, &albedo)?;
+    write_rgba_png(&directory.join(NORMAL_FILE), width as u32, height as u32, &normals)?;
+    let hash_path = directory.join(HASH_FILE);
+    fs::write(&hash_path, trees_hash(config).to_string()).map_err(|e| format!("Failed to write {}: {}", hash_path.display(), e))
+}
+
+/// Impostor quads for a tile's trees. Every vertex carries the middle of its tree's bounds;
+/// tree_impostor.wgsl turns the quad to face the camera.
+#[derive(Default)]
+pub struct ImpostorQuads {
+    centers: Vec<[f32; 3]>,
+    corners: Vec<[f32; 2]>,
+    impostor: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl ImpostorQuads {
+    /// Add a tree with its base at `base` (in tile space), grown at `scale`
+    pub fn push(&mut self, base: Vec3, scale: f32, species: TreeSpecies, bounds: &ImpostorBounds) {
+        let idx = self.centers.len() as u32;
+        let center = base + Vec3::Y * bounds.center_y * scale;
+        self.centers.extend([center.to_array(); 4]);
+        self.corners.extend([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        self.impostor.extend([[bounds.radius * scale, species.index() as f32]; 4]);
+        self.indices.extend([idx, idx + 3, idx + 2, idx, idx + 2, idx + 1]);
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
+        mesh.insert_indices(Indices::U32(self.indices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.centers);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.corners);
+        mesh.insert_attribute(ATTRIBUTE_IMPOSTOR, self.impostor);
+        mesh
+    }
+}
+
+/// Baked views of every species, picked by view direction in tree_impostor.wgsl
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct ImpostorMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub albedo: Handle<Image>,
+    #[texture(2)]
+    #[sampler(3)]
+    pub normal: Handle<Image>,
+}
+
+impl Material for ImpostorMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/tree_impostor.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/tree_impostor.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            ATTRIBUTE_IMPOSTOR.at_shader_location(2),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        // Quads are turned to the camera whichever way their corners wind
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+/// The material every tree tile's impostors share.
+/// It has no asset while the atlases are being baked, and until then impostors aren't drawn.
+#[derive(Resource)]
+pub struct TreeImpostorMaterial(pub Handle<ImpostorMaterial>);
+
+/// Atlases being baked in the background because they were missing or baked from other tree shapes
+#[derive(Resource)]
+struct ImpostorBake(Task<Result<(), String>>);
+
+/// The atlases on disk as a material
+fn load_impostor_material(asset_server: &AssetServer) -> ImpostorMaterial {
+    let directory = IMPOSTOR_DIRECTORY.trim_start_matches("assets/");
+    let albedo = asset_server.load(format!("{}/{}", directory, ALBEDO_FILE));
+    // Normals are data, not colour
+    let normal = asset_server.load_with_settings(format!("{}/{}", directory, NORMAL_FILE), |settings: &mut ImageLoaderSettings| {
+        settings.is_srgb = false;
+    });
+    ImpostorMaterial { albedo, normal }
+}
+
+/// Load the atlases, or start baking them if they're out of date. A bake takes a few seconds, so it runs on the
+/// async compute pool and only the detailed trees show until it's done (see finish_impostor_bake)
+fn setup_impostor_material(
+    mut commands: Commands,
+    config: Res<WorldGenConfig>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ImpostorMaterial>>,
+) {
+    let material = materials.reserve_handle();
+    if impostors_up_to_date(&config, Path::new(IMPOSTOR_DIRECTORY)) {
+        materials.insert(material.id(), load_impostor_material(&asset_server));
+    } else {
+        let config = config.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move { bake_impostors(&config, Path::new(IMPOSTOR_DIRECTORY)) });
+        commands.insert_resource(ImpostorBake(task));
+    }
+    commands.insert_resource(TreeImpostorMaterial(material));
+}
+
+/// Give the impostor material its atlases once the bake is done
+fn finish_impostor_bake(
+    mut commands: Commands,
+    mut bake: ResMut<ImpostorBake>,
+    material: Res<TreeImpostorMaterial>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ImpostorMaterial>>,
+) {
+    let Some(result) = block_on(poll_once(&mut bake.0)) else { return };
+    commands.remove_resource::<ImpostorBake>();
+    match result {
+        Ok(()) => {
+            info!("Baked tree impostors to {}", IMPOSTOR_DIRECTORY);
+            materials.insert(material.0.id(), load_impostor_material(&asset_server));
+        }
+        Err(e) => warn!("Failed to bake tree impostors to {}, distant trees won't show: {}", IMPOSTOR_DIRECTORY, e),
+    }
+}
+
+pub struct TreeImpostorPlugin;
+
+impl Plugin for TreeImpostorPlugin {
+    fn build(&self, app: &mut App) {
+        // The vertex shader places the quads, which the default prepass and shadow shaders don't know how to do
+        app.add_plugins(MaterialPlugin::<ImpostorMaterial> { prepass_enabled: false, ..default() })
+            .add_systems(Startup, setup_impostor_material)
+            .add_systems(Update, finish_impostor_bake.run_if(resource_exists::<ImpostorBake>));
+    }
+}
@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+use crate::entities::tree::{standing_trees, PlacedTree, TreeId, TreeLayer};
+use crate::entities::tree_species::TreeSpecies;
+use crate::util::heightfield::TerrainSampler;
+use crate::util::perlin::setup_perlin;
+use crate::util::streaming::{LayerGenerator, TileKey};
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
+
+// Health of a tree at scale 1; bigger trees take longer to bring down
+pub const TREE_BASE_HEALTH: f32 = 100.0;
+
+/// Everything gameplay needs to know about one tree
+#[derive(Clone, Copy, Debug)]
+pub struct TreeInstance {
+    pub id: TreeId,
+    /// Base of the trunk, on the ground
+    pub position: Vec3,
+    pub species: TreeSpecies,
+    /// Roughly how tall it stands above its base
+    pub height: f32,
+    /// Radius of the trunk at the ground, for obstacles
+    pub trunk_radius: f32,
+    pub health: f32,
+}
+
+/// Where every standing tree in the world is, whether or not its tile is loaded.
+/// Answers come from standing_trees, so they match the trees the tree tiles draw.
+#[derive(Resource, Clone)]
+pub struct TreeIndex {
+    config: WorldGenConfig,
+    sampler: TerrainSampler,
+    deltas: WorldDeltas,
+    /// Typical height of each species at scale 1, indexed by TreeSpecies::index
+    heights: Vec<f32>,
+}
+
+impl TreeIndex {
+    pub fn new(config: &WorldGenConfig, sampler: &TerrainSampler, deltas: &WorldDeltas) -> Self {
+        let heights = TreeSpecies::ALL.iter().map(|species| config.trees.get(*species).typical_height()).collect();
+        Self { config: config.clone(), sampler: sampler.clone(), deltas: deltas.clone(), heights }
+    }
+
+    fn instance(&self, tree: &PlacedTree) -> TreeInstance {
+        let damage = self.deltas.read().tree_damage.get(&tree.id).copied().unwrap_or(0.0);
+        TreeInstance {
+            id: tree.id,
+            position: tree.position,
+            species: tree.species,
+            height: self.heights[tree.species.index()] * tree.scale,
+            trunk_radius: self.config.trees.get(tree.species).trunk_radius * tree.scale,
+            health: TREE_BASE_HEALTH * tree.scale - damage,
+        }
+    }
+
+    /// Every standing tree on one tree tile
+    pub fn tile_trees(&self, key: TileKey) -> Vec<TreeInstance> {
+        standing_trees(&self.config, &self.sampler, &self.deltas, key).iter().map(|tree| self.instance(tree)).collect()
+    }
+
+    /// Where a standing tree was placed, with what it grows from
+    pub fn placement(&self, id: TreeId) -> Option<PlacedTree> {
+        standing_trees(&self.config, &self.sampler, &self.deltas, id.tile).into_iter().find(|tree| tree.id == id)
+    }
+
+    /// One tree, if its slot on its tile grows one and it's still standing
+    pub fn get(&self, id: TreeId) -> Option<TreeInstance> {
+        self.tile_trees(id.tile).into_iter().find(|tree| tree.id == id)
+    }
+
+    /// Every tree standing in the rectangle from `min` to `max` on the xz plane
+    pub fn trees_in(&self, min: Vec2, max: Vec2) -> Vec<TreeInstance> {
+        let settings = TreeLayer::settings();
+        let (min_key, max_key) = (settings.world_to_tile(min.x, min.y), settings.world_to_tile(max.x, max.y));
+        let mut trees = Vec::new();
+        for tile_x in min_key.0..=max_key.0 {
+            for tile_z in min_key.1..=max_key.1 {
+                trees.extend(self.tile_trees((tile_x, tile_z)).into_iter().filter(|tree| {
+                    let p = tree.position.xz();
+                    p.cmpge(min).all() && p.cmple(max).all()
+                }));
+            }
+        }
+        trees
+    }
+
+    /// Every tree within `radius` of `center` on the xz plane
+    pub fn trees_near(&self, center: Vec2, radius: f32) -> Vec<TreeInstance> {
+        self.trees_in(center - radius, center + radius).into_iter()
+            .filter(|tree| tree.position.xz().distance(center) <= radius)
+            .collect()
+    }
+
+    /// The closest tree to `position` on the xz plane, if there's one within `max_distance`
+    pub fn nearest(&self, position: Vec3, max_distance: f32) -> Option<TreeInstance> {
+        let center = position.xz();
+        self.trees_near(center, max_distance).into_iter()
+            .min_by(|a, b| a.position.xz().distance_squared(center).total_cmp(&b.position.xz().distance_squared(center)))
+    }
+
+    /// True if nothing of radius `radius` at `center` would overlap a trunk, e.g. to keep a POI out of a forest
+    pub fn is_clear(&self, center: Vec2, radius: f32) -> bool {
+        // Any trunk that could reach the circle stands within the largest trunk radius of it
+        let max_trunk = TreeSpecies::ALL.iter()
+            .map(|species| {
+                let shape = self.config.trees.get(*species);
+                shape.trunk_radius * shape.scale.1
+            })
+            .fold(0.0, f32::max);
+        self.trees_near(center, radius + max_trunk).iter()
+            .all(|tree| tree.position.xz().distance(center) > radius + tree.trunk_radius)
+    }
+}
+
+fn setup_tree_index(mut commands: Commands, config: Res<WorldGenConfig>, sampler: Res<TerrainSampler>, deltas: Res<WorldDeltas>) {
+    commands.insert_resource(TreeIndex::new(&config, &sampler, &deltas));
+}
+
+/// Stand trees on the ground as it's been edited, each edit swaps in a new sampler
+fn update_tree_index_sampler(mut index: ResMut<TreeIndex>, sampler: Res<TerrainSampler>) {
+    index.sampler = sampler.clone();
+}
+
+pub struct TreeIndexPlugin;
+
+impl Plugin for TreeIndexPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_tree_index.after(setup_perlin))
+            .add_systems(Update, update_tree_index_sampler.run_if(resource_changed::<TerrainSampler>));
+    }
+}
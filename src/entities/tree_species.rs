@@ -0,0 +1,487 @@
+use std::f32::consts::TAU;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::entities::grass::GRASS_BASE_COLOR_2;
+
+// Sides of the prism drawn for each segment; branches are thin enough to get away with fewer
+const TRUNK_SIDES: u32 = 6;
+const BRANCH_SIDES: u32 = 3;
+// Leaf triangles are this wide relative to their length
+const LEAF_WIDTH_RATIO: f32 = 0.4;
+
+/// Which kind of tree grows somewhere. Biomes pick from these; the shapes come from TreeSpeciesConfig.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TreeSpecies {
+    Broadleaf,
+    Conifer,
+    Birch,
+    /// Bare branches, for deserts and tundra
+    Dead,
+}
+
+impl TreeSpecies {
+    /// Every species, in the order their impostors are laid out in the atlas
+    pub const ALL: [TreeSpecies; 4] = [TreeSpecies::Broadleaf, TreeSpecies::Conifer, TreeSpecies::Birch, TreeSpecies::Dead];
+
+    pub fn index(self) -> usize {
+        Self::ALL.iter().position(|species| *species == self).unwrap()
+    }
+}
+
+/// Pick a species from (species, weight) pairs with a roll in 0..1. None if there's nothing to pick.
+pub fn pick_species(choices: &[(TreeSpecies, f32)], roll: f32) -> Option<TreeSpecies> {
+    let total: f32 = choices.iter().map(|(_, weight)| weight).sum();
+    let mut remaining = roll * total;
+    for (species, weight) in choices {
+        if remaining < *weight {
+            return Some(*species);
+        }
+        remaining -= weight;
+    }
+    choices.last().map(|(species, _)| *species)
+}
+
+/// Every species' shape, read from the world gen config so trees can be tweaked without recompiling.
+/// Any species or field left out of the config keeps its built-in value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TreeSpeciesConfig {
+    #[serde(deserialize_with = "broadleaf_shape")]
+    pub broadleaf: TreeShape,
+    #[serde(deserialize_with = "conifer_shape")]
+    pub conifer: TreeShape,
+    #[serde(deserialize_with = "birch_shape")]
+    pub birch: TreeShape,
+    #[serde(deserialize_with = "dead_shape")]
+    pub dead: TreeShape,
+}
+
+impl TreeSpeciesConfig {
+    pub fn get(&self, species: TreeSpecies) -> &TreeShape {
+        match species {
+            TreeSpecies::Broadleaf => &self.broadleaf,
+            TreeSpecies::Conifer => &self.conifer,
+            TreeSpecies::Birch => &self.birch,
+            TreeSpecies::Dead => &self.dead,
+        }
+    }
+
+    /// Roughly the tallest any species grows, at the top of its scale range
+    pub fn max_height(&self) -> f32 {
+        TreeSpecies::ALL.iter()
+            .map(|species| {
+                let shape = self.get(*species);
+                shape.typical_height() * shape.scale.1
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+/// How one species grows: an L-system rewritten `iterations` times from `axiom`, then drawn by a turtle.
+/// Turtle symbols: F grows a segment, L adds a leaf cluster, [ and ] start and end a branch,
+/// & and ^ pitch away from and back towards the parent, / and \ roll around the current axis.
+/// Any other letter is only there for the rules to rewrite.
+/// Read from the config through TreeShapeOverrides, so fields left out keep the species' built-in values.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TreeShape {
+    pub axiom: String,
+    pub rules: Vec<(char, String)>,
+    pub iterations: u32,
+    /// Length of the first trunk segment
+    pub segment_length: f32,
+    pub trunk_radius: f32,
+    /// Length and radius multiplier after each segment, so axes taper towards their tips
+    pub segment_decay: f32,
+    /// Length multiplier on entering a branch
+    pub branch_length: f32,
+    /// Radius multiplier on entering a branch
+    pub branch_radius: f32,
+    /// Radians for & and ^
+    pub branch_angle: f32,
+    /// Radians for / and \
+    pub roll_angle: f32,
+    /// How far each segment bends towards straight up (negative droops), as a fraction of its angle from vertical
+    pub tropism: f32,
+    /// Random fraction every angle and length varies by
+    pub jitter: f32,
+    /// Range of the random size multiplier for a whole tree
+    pub scale: (f32, f32),
+    /// Bark colour at the trunk, fading to bark_tip_color on the outer branches
+    pub bark_color: [f32; 4],
+    pub bark_tip_color: [f32; 4],
+    pub leaves_per_cluster: u32,
+    pub leaf_size: f32,
+    pub leaf_color: [f32; 4],
+    pub leaf_tip_color: [f32; 4],
+}
+
+/// A TreeShape as written in the config, where every field is optional.
+/// Fields are written as plain values, not `Some(...)`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TreeShapeOverrides {
+    #[serde(deserialize_with = "given")]
+    axiom: Option<String>,
+    #[serde(deserialize_with = "given")]
+    rules: Option<Vec<(char, String)>>,
+    #[serde(deserialize_with = "given")]
+    iterations: Option<u32>,
+    #[serde(deserialize_with = "given")]
+    segment_length: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    trunk_radius: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    segment_decay: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    branch_length: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    branch_radius: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    branch_angle: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    roll_angle: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    tropism: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    jitter: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    scale: Option<(f32, f32)>,
+    #[serde(deserialize_with = "given")]
+    bark_color: Option<[f32; 4]>,
+    #[serde(deserialize_with = "given")]
+    bark_tip_color: Option<[f32; 4]>,
+    #[serde(deserialize_with = "given")]
+    leaves_per_cluster: Option<u32>,
+    #[serde(deserialize_with = "given")]
+    leaf_size: Option<f32>,
+    #[serde(deserialize_with = "given")]
+    leaf_color: Option<[f32; 4]>,
+    #[serde(deserialize_with = "given")]
+    leaf_tip_color: Option<[f32; 4]>,
+}
+
+impl TreeShapeOverrides {
+    fn apply(self, base: TreeShape) -> TreeShape {
+        TreeShape {
+            axiom: self.axiom.unwrap_or(base.axiom),
+            rules: self.rules.unwrap_or(base.rules),
+            iterations: self.iterations.unwrap_or(base.iterations),
+            segment_length: self.segment_length.unwrap_or(base.segment_length),
+            trunk_radius: self.trunk_radius.unwrap_or(base.trunk_radius),
+            segment_decay: self.segment_decay.unwrap_or(base.segment_decay),
+            branch_length: self.branch_length.unwrap_or(base.branch_length),
+            branch_radius: self.branch_radius.unwrap_or(base.branch_radius),
+            branch_angle: self.branch_angle.unwrap_or(base.branch_angle),
+            roll_angle: self.roll_angle.unwrap_or(base.roll_angle),
+            tropism: self.tropism.unwrap_or(base.tropism),
+            jitter: self.jitter.unwrap_or(base.jitter),
+            scale: self.scale.unwrap_or(base.scale),
+            bark_color: self.bark_color.unwrap_or(base.bark_color),
+            bark_tip_color: self.bark_tip_color.unwrap_or(base.bark_tip_color),
+            leaves_per_cluster: self.leaves_per_cluster.unwrap_or(base.leaves_per_cluster),
+            leaf_size: self.leaf_size.unwrap_or(base.leaf_size),
+            leaf_color: self.leaf_color.unwrap_or(base.leaf_color),
+            leaf_tip_color: self.leaf_tip_color.unwrap_or(base.leaf_tip_color),
+        }
+    }
+}
+
+/// A field that's in the config, see TreeShapeOverrides
+fn given<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Read a species' shape over its built-in one
+fn shape_over<'de, D: Deserializer<'de>>(deserializer: D, species: TreeSpecies) -> Result<TreeShape, D::Error> {
+    let base = TreeSpeciesConfig::default().get(species).clone();
+    Ok(TreeShapeOverrides::deserialize(deserializer)?.apply(base))
+}
+
+fn broadleaf_shape<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TreeShape, D::Error> {
+    shape_over(deserializer, TreeSpecies::Broadleaf)
+}
+
+fn conifer_shape<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TreeShape, D::Error> {
+    shape_over(deserializer, TreeSpecies::Conifer)
+}
+
+fn birch_shape<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TreeShape, D::Error> {
+    shape_over(deserializer, TreeSpecies::Birch)
+}
+
+fn dead_shape<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TreeShape, D::Error> {
+    shape_over(deserializer, TreeSpecies::Dead)
+}
+
+/// Triangles for one tree, with indices starting from 0
+#[derive(Default)]
+pub struct TreeGeometry {
+    pub verts: Vec<Vec3>,
+    pub indices: Vec<u32>,
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl TreeGeometry {
+    fn triangle(&mut self, corners: [Vec3; 3], colors: [[f32; 4]; 3]) {
+        let idx = self.verts.len() as u32;
+        self.verts.extend(corners);
+        self.colors.extend(colors);
+        self.indices.extend([idx, idx + 1, idx + 2]);
+    }
+
+    /// Open-ended prism from one ring to the next, both facing the turtle's heading
+    fn segment(&mut self, start: Vec3, end: Vec3, rotation: Quat, radii: (f32, f32), sides: u32, color: [f32; 4]) {
+        let base_index = self.verts.len() as u32;
+        for i in 0..sides {
+            let angle = i as f32 / sides as f32 * TAU;
+            let out = rotation * Vec3::new(angle.cos(), 0.0, angle.sin());
+            self.verts.extend([start + out * radii.0, end + out * radii.1]);
+            self.colors.extend([color; 2]);
+        }
+        for i in 0..sides {
+            let next = (i + 1) % sides;
+            let (b0, t0) = (base_index + i * 2, base_index + i * 2 + 1);
+            let (b1, t1) = (base_index + next * 2, base_index + next * 2 + 1);
+            self.indices.extend([b0, b1, t1, b0, t1, t0]);
+        }
+    }
+}
+
+/// Where the turtle is and what it's drawing with
+#[derive(Clone, Copy)]
+struct Turtle {
+    position: Vec3,
+    /// +Y is the heading
+    rotation: Quat,
+    length: f32,
+    radius: f32,
+    depth: u32,
+}
+
+impl TreeShape {
+    /// The L-system string after `iterations` rounds of rewriting
+    pub fn expand(&self) -> String {
+        let mut current = self.axiom.clone();
+        for _ in 0..self.iterations {
+            let mut next = String::with_capacity(current.len() * 2);
+            for symbol in current.chars() {
+                match self.rules.iter().find(|(from, _)| *from == symbol) {
+                    Some((_, to)) => next.push_str(to),
+                    None => next.push(symbol),
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    /// Height of a tree grown at scale 1 from a fixed seed. Single trees vary around it by `jitter`.
+    pub fn typical_height(&self) -> f32 {
+        self.generate(&mut StdRng::seed_from_u64(0), Vec3::ZERO, 1.0).verts.iter().map(|v| v.y).fold(0.0, f32::max)
+    }
+
+    fn vary(&self, rng: &mut impl Rng) -> f32 {
+        // A negative jitter from a config file would make an empty range
+        let jitter = self.jitter.abs();
+        1.0 + rng.gen_range(-jitter..=jitter)
+    }
+
+    /// Grow one tree from `base`, scaled by `scale`. Everything random comes from `rng`.
+    pub fn generate(&self, rng: &mut impl Rng, base: Vec3, scale: f32) -> TreeGeometry {
+        let mut geometry = TreeGeometry::default();
+        let mut turtle = Turtle {
+            position: base,
+            rotation: Quat::from_rotation_y(rng.gen_range(0.0..TAU)),
+            length: self.segment_length * scale,
+            radius: self.trunk_radius * scale,
+            depth: 0,
+        };
+        let mut stack = vec![];
+
+        for symbol in self.expand().chars() {
+            match symbol {
+                'F' => {
+                    let heading = turtle.rotation * Vec3::Y;
+                    let end = turtle.position + heading * turtle.length * self.vary(rng);
+                    let end_radius = turtle.radius * self.segment_decay;
+                    let sides = if turtle.depth == 0 { TRUNK_SIDES } else { BRANCH_SIDES };
+                    let color = color_lerp(self.bark_color, self.bark_tip_color, (turtle.depth as f32 / 3.0).min(1.0));
+                    geometry.segment(turtle.position, end, turtle.rotation, (turtle.radius, end_radius), sides, color);
+                    turtle.position = end;
+                    turtle.radius = end_radius;
+                    turtle.length *= self.segment_decay;
+
+                    // Bend towards (or away from) vertical
+                    let axis = heading.cross(Vec3::Y);
+                    if axis.length_squared() > 1e-6 {
+                        let bend = Quat::from_axis_angle(axis.normalize(), self.tropism * heading.angle_between(Vec3::Y));
+                        turtle.rotation = bend * turtle.rotation;
+                    }
+                }
+                'L' => {
+                    let heading = turtle.rotation * Vec3::Y;
+                    let size = self.leaf_size * scale;
+                    for _ in 0..self.leaves_per_cluster {
+                        let scatter = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                        let direction = (heading * 0.5 + scatter).normalize_or_zero();
+                        if direction == Vec3::ZERO {
+                            continue;
+                        }
+                        let side = direction.any_orthonormal_vector() * size * LEAF_WIDTH_RATIO / 2.0;
+                        let p = turtle.position;
+                        geometry.triangle(
+                            [p + side, p - side, p + direction * size * self.vary(rng)],
+                            [self.leaf_color, self.leaf_color, self.leaf_tip_color],
+                        );
+                    }
+                }
+                '[' => {
+                    stack.push(turtle);
+                    turtle.length *= self.branch_length;
+                    turtle.radius *= self.branch_radius;
+                    turtle.depth += 1;
+                }
+                ']' => {
+                    if let Some(parent) = stack.pop() {
+                        turtle = parent;
+                    }
+                }
+                '&' => turtle.rotation *= Quat::from_rotation_x(self.branch_angle * self.vary(rng)),
+                '^' => turtle.rotation *= Quat::from_rotation_x(-self.branch_angle * self.vary(rng)),
+                '/' => turtle.rotation *= Quat::from_rotation_y(self.roll_angle * self.vary(rng)),
+                '\\' => turtle.rotation *= Quat::from_rotation_y(-self.roll_angle * self.vary(rng)),
+                _ => {}
+            }
+        }
+        geometry
+    }
+}
+
+fn color_lerp(c1: [f32; 4], c2: [f32; 4], t: f32) -> [f32; 4] {
+    [
+        c1[0] + (c2[0] - c1[0]) * t,
+        c1[1] + (c2[1] - c1[1]) * t,
+        c1[2] + (c2[2] - c1[2]) * t,
+        c1[3] + (c2[3] - c1[3]) * t,
+    ]
+}
+
+fn rules(rules: &[(char, &str)]) -> Vec<(char, String)> {
+    rules.iter().map(|(from, to)| (*from, to.to_string())).collect()
+}
+
+impl Default for TreeSpeciesConfig {
+    fn default() -> Self {
+        Self {
+            // A trunk that forks three ways at every level into a wide crown
+            broadleaf: TreeShape {
+                axiom: "FFA".into(),
+                rules: rules(&[('A', "[&FLA]/[&FLA]/[&FLA]")]),
+                iterations: 4,
+                segment_length: 2.5,
+                trunk_radius: 0.35,
+                segment_decay: 0.9,
+                branch_length: 0.8,
+                branch_radius: 0.6,
+                branch_angle: 0.6,
+                roll_angle: 2.4,
+                tropism: 0.05,
+                jitter: 0.15,
+                scale: (0.8, 1.2),
+                bark_color: [0.22, 0.15, 0.08, 1.0],
+                bark_tip_color: [0.16, 0.12, 0.07, 1.0],
+                leaves_per_cluster: 6,
+                leaf_size: 1.2,
+                leaf_color: [0.01, 0.03, 0.0, 1.0],
+                leaf_tip_color: [0.05, 0.09, 0.01, 1.0],
+            },
+            // Whorls of drooping, needled branches up a straight trunk; lower whorls have grown longer
+            conifer: TreeShape {
+                axiom: "FFAL".into(),
+                rules: rules(&[('A', "F[&B]/[&B]/[&B]/[&B]/A"), ('B', "FLB")]),
+                iterations: 8,
+                segment_length: 1.5,
+                trunk_radius: 0.3,
+                segment_decay: 0.92,
+                branch_length: 0.8,
+                branch_radius: 0.3,
+                branch_angle: 1.3,
+                roll_angle: TAU / 4.0,
+                tropism: -0.1,
+                jitter: 0.15,
+                scale: (0.8, 1.3),
+                bark_color: [0.30, 0.18, 0.08, 1.0],
+                bark_tip_color: GRASS_BASE_COLOR_2,
+                leaves_per_cluster: 4,
+                leaf_size: 1.6,
+                leaf_color: [0.10, 0.088, 0.034, 1.0],
+                leaf_tip_color: [0.042, 0.047, 0.014, 1.0],
+            },
+            // Tall and slender with pale bark and short upswept branches
+            birch: TreeShape {
+                axiom: "FFFA".into(),
+                rules: rules(&[('A', "F[&B]/A"), ('B', "FLB")]),
+                iterations: 9,
+                segment_length: 1.6,
+                trunk_radius: 0.18,
+                segment_decay: 0.93,
+                branch_length: 0.6,
+                branch_radius: 0.4,
+                branch_angle: 0.5,
+                roll_angle: 2.4,
+                tropism: -0.05,
+                jitter: 0.2,
+                scale: (0.8, 1.2),
+                bark_color: [0.55, 0.53, 0.50, 1.0],
+                bark_tip_color: [0.20, 0.18, 0.15, 1.0],
+                leaves_per_cluster: 6,
+                leaf_size: 0.8,
+                leaf_color: [0.05, 0.09, 0.01, 1.0],
+                leaf_tip_color: [0.12, 0.16, 0.03, 1.0],
+            },
+            // A few crooked forks and no leaves
+            dead: TreeShape {
+                axiom: "FFA".into(),
+                rules: rules(&[('A', "[&FA]/[&FA]")]),
+                iterations: 4,
+                segment_length: 2.0,
+                trunk_radius: 0.3,
+                segment_decay: 0.85,
+                branch_length: 0.75,
+                branch_radius: 0.6,
+                branch_angle: 0.7,
+                roll_angle: 1.9,
+                tropism: 0.0,
+                jitter: 0.35,
+                scale: (0.7, 1.1),
+                bark_color: [0.20, 0.16, 0.12, 1.0],
+                bark_tip_color: [0.35, 0.32, 0.28, 1.0],
+                leaves_per_cluster: 0,
+                leaf_size: 0.0,
+                leaf_color: [0.0; 4],
+                leaf_tip_color: [0.0; 4],
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_left_out_keep_the_species_defaults() {
+        let defaults = TreeSpeciesConfig::default();
+        let config: TreeSpeciesConfig = ron::from_str("(dead: (iterations: 5), birch: (scale: (1.0, 1.6)))").unwrap();
+        assert_eq!(config.dead, TreeShape { iterations: 5, ..defaults.dead.clone() });
+        assert_eq!(config.birch, TreeShape { scale: (1.0, 1.6), ..defaults.birch.clone() });
+        assert_eq!(config.broadleaf, defaults.broadleaf);
+        assert_eq!(config.conifer, defaults.conifer);
+
+        // Saves write every field out, and read back the same
+        let written = ron::to_string(&config).unwrap();
+        assert_eq!(ron::from_str::<TreeSpeciesConfig>(&written).unwrap(), config);
+    }
+}
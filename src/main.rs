@@ -9,9 +9,51 @@ use bevy_rapier3d::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_shader_utils::ShaderUtilsPlugin;
 use crate::entities as ent;
+use crate::util::{cli::{CliArgs, Command}, save::{PendingLoad, SaveGame}, world_delta::WorldDeltas, worldgen::WorldGenConfig};
 
 fn main() {
-    App::new()
+    let cli = CliArgs::parse().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // A save brings its own world along
+    let save = cli.load.as_deref().map(|path| SaveGame::load(path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }));
+    let world_gen_config = match &save {
+        Some(save) => save.world.clone(),
+        None => WorldGenConfig::from_cli(&cli).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+    };
+
+    // Headless subcommands run without opening a window
+    match &cli.command {
+        Some(Command::Export(export)) => {
+            if let Err(e) = util::export::run_export(&world_gen_config, export) {
+                eprintln!("Export failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::BakeImpostors(bake)) => {
+            if let Err(e) = ent::tree_impostor::run_bake(&world_gen_config, bake) {
+                eprintln!("Impostor bake failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+
+    let mut app = App::new();
+    if let Some(save) = save {
+        app.insert_resource(WorldDeltas::new(save.delta.clone()))
+            .insert_resource(PendingLoad(save));
+    }
+    app.insert_resource(world_gen_config)
         .add_plugins((
             (
                 DefaultPlugins,
@@ -28,6 +70,10 @@ fn main() {
             // util::audio::AudioPlugin,
             util::lighting::LightingPlugin,
             util::perlin::PerlinPlugin,
+            util::wind::WindPlugin,
+            util::world_delta::WorldDeltaPlugin,
+            util::height_delta::HeightDeltaPlugin,
+            util::save::SavePlugin,
             ent::terrain::TerrainPlugin,
             ent::grass::GrassPlugin,
             ent::tree::TreePlugin,
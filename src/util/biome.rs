@@ -0,0 +1,279 @@
+use noise::{NoiseFn, Perlin};
+use crate::entities::grass::{GRASS_BASE_COLOR_2, GRASS_SECOND_COLOR};
+use crate::entities::tree_species::TreeSpecies;
+use crate::util::worldgen::WorldGenConfig;
+
+// Climate thresholds (temperature and moisture are roughly -1..1)
+const TUNDRA_TEMPERATURE: f32 = -0.3;
+const DESERT_TEMPERATURE: f32 = 0.25;
+const DESERT_MOISTURE: f32 = -0.1;
+const FOREST_MOISTURE: f32 = 0.1;
+// How much colder it gets going from the bottom to the top of the temperate band
+const ALTITUDE_COOLING: f32 = 0.6;
+// Slopes steeper than this (radians) are bare rock regardless of climate
+const ALPINE_SLOPE: f32 = 0.9;
+// Width of the smooth blend either side of a climate threshold
+const BLEND_WIDTH: f32 = 0.1;
+// Rock starts showing through (and grass thinning out) this many radians below ALPINE_SLOPE
+const ROCK_SLOPE_BLEND: f32 = 0.2;
+// Grass thins out over this many world units below height_temperate_end
+const GRASS_ALTITUDE_FADE: f32 = 150.0;
+// How far a blade's root colour is pulled towards the ground under it, so meadows blend into sand and rock
+const GRASS_ROOT_GROUND_BLEND: f32 = 0.7;
+
+const COLOR_SAND: [f32;4] = [80./255., 72./255., 49./255., 255./255.];
+const COLOR_PEAKS: [f32;4] = [255./255.,255./255.,255./255.,255./255.];
+const COLOR_ROCK: [f32;4] = [0.18, 0.17, 0.16, 1.0];
+
+/// Broad classification of a point on the terrain.
+/// Terrain colour, grass and trees all read from the same classification so they agree with each other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Biome {
+    Beach,
+    Meadow,
+    Forest,
+    Desert,
+    Tundra,
+    Alpine,
+}
+
+/// Everything a biome contributes to the generators
+pub struct BiomeParams {
+    pub terrain_color: [f32; 4],
+    /// Fraction of grass blades kept (0 = no grass)
+    pub grass_density: f32,
+    /// Multiplier on grass blade height
+    pub grass_height: f32,
+    pub grass_base_color: [f32; 4],
+    pub grass_tip_color: [f32; 4],
+    /// Fraction of tree placement attempts kept (0 = no trees)
+    pub tree_density: f32,
+    /// Species that grow here, with relative weights
+    pub tree_species: &'static [(TreeSpecies, f32)],
+}
+
+const BEACH: BiomeParams = BiomeParams {
+    terrain_color: COLOR_SAND,
+    grass_density: 0.0,
+    grass_height: 0.0,
+    grass_base_color: GRASS_BASE_COLOR_2,
+    grass_tip_color: GRASS_SECOND_COLOR,
+    tree_density: 0.0,
+    tree_species: &[],
+};
+
+const MEADOW: BiomeParams = BiomeParams {
+    terrain_color: GRASS_SECOND_COLOR,
+    grass_density: 1.0,
+    grass_height: 1.0,
+    grass_base_color: GRASS_BASE_COLOR_2,
+    grass_tip_color: GRASS_SECOND_COLOR,
+    tree_density: 0.1,
+    tree_species: &[(TreeSpecies::Broadleaf, 0.6), (TreeSpecies::Birch, 0.4)],
+};
+
+const FOREST: BiomeParams = BiomeParams {
+    terrain_color: [0.05,0.055,0.01,1.],
+    grass_density: 0.5,
+    grass_height: 0.8,
+    grass_base_color: [0.,0.015,0.,1.],
+    grass_tip_color: [0.04,0.06,0.01,1.],
+    tree_density: 1.0,
+    tree_species: &[(TreeSpecies::Conifer, 0.6), (TreeSpecies::Broadleaf, 0.25), (TreeSpecies::Birch, 0.15)],
+};
+
+const DESERT: BiomeParams = BiomeParams {
+    terrain_color: [0.45, 0.36, 0.2, 1.0],
+    grass_density: 0.05,
+    grass_height: 0.6,
+    grass_base_color: [0.12,0.09,0.03,1.],
+    grass_tip_color: [0.3,0.25,0.1,1.],
+    tree_density: 0.05,
+    tree_species: &[(TreeSpecies::Dead, 1.0)],
+};
+
+const TUNDRA: BiomeParams = BiomeParams {
+    terrain_color: [0.3, 0.32, 0.3, 1.0],
+    grass_density: 0.3,
+    grass_height: 0.5,
+    grass_base_color: [0.03,0.04,0.02,1.],
+    grass_tip_color: [0.15,0.14,0.08,1.],
+    tree_density: 0.15,
+    tree_species: &[(TreeSpecies::Conifer, 0.5), (TreeSpecies::Dead, 0.5)],
+};
+
+const ALPINE: BiomeParams = BiomeParams {
+    terrain_color: COLOR_ROCK,
+    grass_density: 0.0,
+    grass_height: 0.0,
+    grass_base_color: GRASS_BASE_COLOR_2,
+    grass_tip_color: GRASS_SECOND_COLOR,
+    tree_density: 0.0,
+    tree_species: &[],
+};
+
+/// Grass at a point, blended across biomes (see grass_cover)
+pub struct GrassCover {
+    /// Fraction of grass blades kept (0 = no grass)
+    pub density: f32,
+    /// Multiplier on grass blade height
+    pub height: f32,
+    pub base_color: [f32; 4],
+    pub tip_color: [f32; 4],
+}
+
+/// Temperature and moisture at a point, roughly -1..1 each
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Climate {
+    pub temperature: f32,
+    pub moisture: f32,
+}
+
+/// Low frequency noise fields for temperature and moisture
+pub struct ClimateSampler {
+    temperature: Perlin,
+    moisture: Perlin,
+    scale: f64,
+}
+
+impl ClimateSampler {
+    pub fn new(config: &WorldGenConfig) -> Self {
+        Self {
+            temperature: Perlin::new(config.temperature_seed),
+            moisture: Perlin::new(config.moisture_seed),
+            scale: config.climate_scale as f64,
+        }
+    }
+
+    pub fn sample(&self, x: f32, z: f32) -> Climate {
+        let p = [x as f64 / self.scale, z as f64 / self.scale];
+        Climate {
+            temperature: self.temperature.get(p) as f32 * 2.0,
+            moisture: self.moisture.get(p) as f32 * 2.0,
+        }
+    }
+}
+
+impl Biome {
+    /// Classify a point from its climate, height and slope (radians)
+    pub fn classify(config: &WorldGenConfig, climate: Climate, height: f32, slope: f32) -> Self {
+        if height < config.height_temperate_start {
+            return Biome::Beach;
+        }
+        if height > config.height_temperate_end || slope > ALPINE_SLOPE {
+            return Biome::Alpine;
+        }
+        let temperature = effective_temperature(config, climate, height);
+        if temperature < TUNDRA_TEMPERATURE {
+            Biome::Tundra
+        } else if temperature > DESERT_TEMPERATURE && climate.moisture < DESERT_MOISTURE {
+            Biome::Desert
+        } else if climate.moisture > FOREST_MOISTURE {
+            Biome::Forest
+        } else {
+            Biome::Meadow
+        }
+    }
+
+    pub fn params(&self) -> &'static BiomeParams {
+        match self {
+            Biome::Beach => &BEACH,
+            Biome::Meadow => &MEADOW,
+            Biome::Forest => &FOREST,
+            Biome::Desert => &DESERT,
+            Biome::Tundra => &TUNDRA,
+            Biome::Alpine => &ALPINE,
+        }
+    }
+}
+
+/// Temperature drops with altitude across the temperate band
+fn effective_temperature(config: &WorldGenConfig, climate: Climate, height: f32) -> f32 {
+    let altitude = ((height - config.height_temperate_start) / (config.height_temperate_end - config.height_temperate_start)).clamp(0.0, 1.0);
+    climate.temperature - altitude * ALTITUDE_COOLING
+}
+
+/// Blend one parameter of the four climate driven biomes (meadow, forest, desert, tundra).
+/// Uses the same thresholds as Biome::classify, but blends smoothly across them so there are no hard seams.
+fn blend_climate<T>(
+    config: &WorldGenConfig,
+    climate: Climate,
+    height: f32,
+    pick: impl Fn(&BiomeParams) -> T,
+    lerp: impl Fn(T, T, f32) -> T,
+) -> T {
+    let temperature = effective_temperature(config, climate, height);
+    let forest_w = smoothstep(FOREST_MOISTURE - BLEND_WIDTH, FOREST_MOISTURE + BLEND_WIDTH, climate.moisture);
+    let mut value = lerp(pick(&MEADOW), pick(&FOREST), forest_w);
+    let desert_w = smoothstep(DESERT_TEMPERATURE - BLEND_WIDTH, DESERT_TEMPERATURE + BLEND_WIDTH, temperature)
+        * (1.0 - smoothstep(DESERT_MOISTURE - BLEND_WIDTH, DESERT_MOISTURE + BLEND_WIDTH, climate.moisture));
+    value = lerp(value, pick(&DESERT), desert_w);
+    let tundra_w = 1.0 - smoothstep(TUNDRA_TEMPERATURE - BLEND_WIDTH, TUNDRA_TEMPERATURE + BLEND_WIDTH, temperature);
+    lerp(value, pick(&TUNDRA), tundra_w)
+}
+
+/// Terrain colour at a point, blended smoothly across biome thresholds
+pub fn terrain_color(config: &WorldGenConfig, climate: Climate, height: f32, slope: f32) -> [f32; 4] {
+    let mut ground = blend_climate(config, climate, height, |params| params.terrain_color, color_lerp);
+
+    // Steep slopes show rock
+    let rock_w = smoothstep(ALPINE_SLOPE - ROCK_SLOPE_BLEND, ALPINE_SLOPE, slope);
+    ground = color_lerp(ground, COLOR_ROCK, rock_w);
+
+    // Height bands: sand at the shore, ground through the temperate band, rock then snow above it
+    if height < config.height_sand {
+        COLOR_SAND
+    } else if height > config.height_peaks {
+        COLOR_PEAKS
+    } else if height < config.height_temperate_start {
+        color_lerp(COLOR_SAND, ground, (height - config.height_sand) / (config.height_temperate_start - config.height_sand))
+    } else if height < config.height_temperate_end {
+        ground
+    } else {
+        let t = (height - config.height_temperate_end) / (config.height_peaks - config.height_temperate_end);
+        if t < 0.5 {
+            color_lerp(ground, COLOR_ROCK, t * 2.0)
+        } else {
+            color_lerp(COLOR_ROCK, COLOR_PEAKS, t * 2.0 - 1.0)
+        }
+    }
+}
+
+/// Grass at a point. Blends across biomes like terrain_color, and rather than stopping at the edge of the
+/// temperate band or at ALPINE_SLOPE, thins out and shortens smoothly towards sand, rock and high altitude.
+/// Roots take on the colour of the ground beneath them.
+pub fn grass_cover(config: &WorldGenConfig, climate: Climate, height: f32, slope: f32) -> GrassCover {
+    let shore = smoothstep(config.height_sand, config.height_temperate_start, height);
+    let altitude = 1.0 - smoothstep(config.height_temperate_end - GRASS_ALTITUDE_FADE, config.height_temperate_end, height);
+    let steepness = 1.0 - smoothstep(ALPINE_SLOPE - ROCK_SLOPE_BLEND, ALPINE_SLOPE, slope);
+    let fade = shore * altitude * steepness;
+
+    let density = blend_climate(config, climate, height, |params| params.grass_density, lerp);
+    let grass_height = blend_climate(config, climate, height, |params| params.grass_height, lerp);
+    let base_color = blend_climate(config, climate, height, |params| params.grass_base_color, color_lerp);
+    let ground = terrain_color(config, climate, height, slope);
+    GrassCover {
+        density: density * fade,
+        height: grass_height * (0.5 + 0.5 * fade),
+        base_color: color_lerp(base_color, ground, GRASS_ROOT_GROUND_BLEND),
+        tip_color: blend_climate(config, climate, height, |params| params.grass_tip_color, color_lerp),
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn color_lerp(c1: [f32; 4], c2: [f32; 4], t: f32) -> [f32; 4] {
+    [
+        c1[0] + (c2[0] - c1[0]) * t,
+        c1[1] + (c2[1] - c1[1]) * t,
+        c1[2] + (c2[2] - c1[2]) * t,
+        c1[3] + (c2[3] - c1[3]) * t,
+    ]
+}
@@ -0,0 +1,165 @@
+/// Command line arguments.
+/// Kept deliberately small (no clap) - flags are `--name value` pairs,
+/// optionally after a subcommand (`first-game export ...`, `first-game bake-impostors ...`).
+#[derive(Default, Debug, Clone)]
+pub struct CliArgs {
+    /// Path to a world gen config file (defaults to worldgen::DEFAULT_CONFIG_PATH)
+    pub config_path: Option<String>,
+    /// Overrides the terrain seed from the config file
+    pub seed: Option<u32>,
+    /// Save file to continue from; its world replaces the config file and --seed
+    pub load: Option<String>,
+    /// Headless subcommand to run instead of the game
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Export(ExportArgs),
+    BakeImpostors(BakeImpostorsArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Png16,
+    Obj,
+    Gltf,
+}
+
+/// `export --region x0,z0,x1,z1 --format png16|obj|gltf [--out path]`
+#[derive(Debug, Clone)]
+pub struct ExportArgs {
+    /// Inclusive range of chunk coordinates: (x0, z0, x1, z1)
+    pub region: (i32, i32, i32, i32),
+    pub format: ExportFormat,
+    /// Output file, defaults to `terrain.<extension>`
+    pub output: Option<String>,
+}
+
+/// `bake-impostors [--out directory]`
+#[derive(Debug, Clone, Default)]
+pub struct BakeImpostorsArgs {
+    /// Directory for the atlases, defaults to tree_impostor::IMPOSTOR_DIRECTORY
+    pub output: Option<String>,
+}
+
+impl Default for ExportArgs {
+    fn default() -> Self {
+        Self { region: (-1, -1, 1, 1), format: ExportFormat::Png16, output: None }
+    }
+}
+
+impl CliArgs {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    /// Parse arguments (without the program name). Missing or malformed values are errors, unknown flags are ignored.
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("export") => {
+                args.next();
+                cli.command = Some(Command::Export(ExportArgs::default()));
+            }
+            Some("bake-impostors") => {
+                args.next();
+                cli.command = Some(Command::BakeImpostors(BakeImpostorsArgs::default()));
+            }
+            _ => {}
+        }
+        while let Some(arg) = args.next() {
+            let mut value = |expected: &str| args.next().ok_or_else(|| format!("{} requires {}", arg, expected));
+            match (arg.as_str(), &mut cli.command) {
+                ("--config", _) => cli.config_path = Some(value("a config file")?),
+                ("--load", _) => cli.load = Some(value("a save file")?),
+                ("--seed", _) => {
+                    let seed = value("a seed")?;
+                    cli.seed = Some(seed.parse().map_err(|_| format!("Invalid seed: {}", seed))?);
+                }
+                ("--region", Some(Command::Export(export))) => {
+                    let region = value("x0,z0,x1,z1")?;
+                    export.region = parse_region(&region).ok_or_else(|| format!("Invalid region: {}", region))?;
+                }
+                ("--format", Some(Command::Export(export))) => {
+                    let format = value("png16, obj or gltf")?;
+                    export.format = parse_format(&format).ok_or_else(|| format!("Invalid export format: {}", format))?;
+                }
+                ("--out", Some(Command::Export(export))) => export.output = Some(value("a path")?),
+                ("--out", Some(Command::BakeImpostors(bake))) => bake.output = Some(value("a path")?),
+                (other, _) => println!("Ignoring unknown argument: {}", other),
+            }
+        }
+        Ok(cli)
+    }
+}
+
+fn parse_format(value: &str) -> Option<ExportFormat> {
+    match value {
+        "png16" => Some(ExportFormat::Png16),
+        "obj" => Some(ExportFormat::Obj),
+        "gltf" => Some(ExportFormat::Gltf),
+        _ => None,
+    }
+}
+
+/// Parse `x0,z0,x1,z1`, putting the corners in order
+fn parse_region(value: &str) -> Option<(i32, i32, i32, i32)> {
+    let parts: Vec<i32> = value.split(',').map(|p| p.trim().parse().ok()).collect::<Option<_>>()?;
+    let [x0, z0, x1, z1] = parts[..] else { return None };
+    Some((x0.min(x1), z0.min(z1), x0.max(x1), z0.max(z1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn export_args(args: &[&str]) -> ExportArgs {
+        match parse(args).unwrap().command {
+            Some(Command::Export(export)) => export,
+            other => panic!("expected an export command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn region_corners_are_put_in_order() {
+        assert_eq!(parse_region("-2,-2,2,2"), Some((-2, -2, 2, 2)));
+        assert_eq!(parse_region("3, 1, -1, 0"), Some((-1, 0, 3, 1)));
+        assert_eq!(parse_region("1,2,3"), None);
+        assert_eq!(parse_region("1,2,3,4,5"), None);
+        assert_eq!(parse_region("a,b,c,d"), None);
+    }
+
+    #[test]
+    fn export_flags_are_parsed() {
+        let export = export_args(&["export", "--region", "0,0,3,3", "--format", "gltf", "--out", "terrain.gltf"]);
+        assert_eq!(export.region, (0, 0, 3, 3));
+        assert_eq!(export.format, ExportFormat::Gltf);
+        assert_eq!(export.output.as_deref(), Some("terrain.gltf"));
+        assert_eq!(export_args(&["export", "--format", "obj"]).format, ExportFormat::Obj);
+        assert_eq!(export_args(&["export"]).format, ExportFormat::Png16);
+
+        let cli = parse(&["export", "--seed", "1234", "--format", "png16"]).unwrap();
+        assert_eq!(cli.seed, Some(1234));
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        for args in [
+            &["export", "--format", "tiff"][..],
+            &["export", "--format"],
+            &["export", "--region", "0,0,3"],
+            &["export", "--region"],
+            &["--seed", "-1"],
+            &["--seed"],
+            &["--load"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} should fail to parse", args);
+        }
+    }
+}
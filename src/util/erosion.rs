@@ -0,0 +1,276 @@
+use std::sync::Arc;
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use crate::entities::terrain::CHUNK_SIZE;
+use crate::util::biome::Climate;
+use crate::util::heightfield::{blend_tiles, HeightSampler, TileCache};
+use crate::util::worldgen::WorldGenConfig;
+
+// Eroded tiles kept around before the oldest are dropped
+const MAX_CACHED_TILES: usize = 1024;
+
+/// Settings for the optional erosion post-pass.
+/// Erosion runs on one padded tile per chunk, so the padding must be wide enough for droplets
+/// to carve the same valleys either side of a chunk border.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErosionConfig {
+    /// Grid cells along one side of a chunk
+    pub resolution: usize,
+    /// Extra cells simulated around each chunk
+    pub padding: usize,
+
+    // Hydraulic erosion (water droplets)
+    pub droplets: u32,
+    pub droplet_lifetime: u32,
+    pub inertia: f32,
+    pub sediment_capacity: f32,
+    pub min_sediment_capacity: f32,
+    pub erode_speed: f32,
+    pub deposit_speed: f32,
+    pub evaporate_speed: f32,
+    pub gravity: f32,
+
+    // Thermal erosion (slumping of slopes steeper than the talus angle, in radians)
+    pub thermal_iterations: u32,
+    pub talus_angle: f32,
+    pub thermal_rate: f32,
+}
+
+impl Default for ErosionConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 64,
+            padding: 16,
+            droplets: 20000,
+            droplet_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.01,
+            gravity: 4.0,
+            thermal_iterations: 10,
+            talus_angle: 0.6,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+impl ErosionConfig {
+    /// Reject settings the simulation can't run with
+    pub fn validate(&self) -> Result<(), String> {
+        if self.resolution == 0 {
+            return Err("erosion resolution must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Height change from erosion over one padded chunk tile
+struct ErodedTile {
+    origin_x: f32,
+    origin_z: f32,
+    cell_size: f32,
+    size: usize,
+    delta: Vec<f32>,
+}
+
+impl ErodedTile {
+    /// Bilinear sample of the height change at a world position covered by this tile
+    fn delta_at(&self, x: f32, z: f32) -> f32 {
+        let gx = ((x - self.origin_x) / self.cell_size).clamp(0.0, (self.size - 1) as f32);
+        let gz = ((z - self.origin_z) / self.cell_size).clamp(0.0, (self.size - 1) as f32);
+        let ix = (gx as usize).min(self.size - 2);
+        let iz = (gz as usize).min(self.size - 2);
+        let fx = gx - ix as f32;
+        let fz = gz - iz as f32;
+        let h = |i: usize, j: usize| self.delta[j * self.size + i];
+        let top = h(ix, iz) * (1.0 - fx) + h(ix + 1, iz) * fx;
+        let bottom = h(ix, iz + 1) * (1.0 - fx) + h(ix + 1, iz + 1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+}
+
+/// Wraps another sampler and adds the erosion pass on top.
+/// Tiles are eroded lazily the first time any part of a chunk is sampled and then cached,
+/// so a chunk regenerating at a different LOD reuses the same tile.
+pub struct ErodedHeightSampler {
+    base: Arc<dyn HeightSampler>,
+    erosion: ErosionConfig,
+    cache: TileCache<ErodedTile>,
+}
+
+impl ErodedHeightSampler {
+    pub fn new(base: Arc<dyn HeightSampler>, erosion: ErosionConfig) -> Self {
+        Self { base, erosion, cache: TileCache::new(MAX_CACHED_TILES) }
+    }
+
+    /// Height change at (x, z), cross-faded between neighbouring chunk tiles over half the padding
+    fn delta(&self, x: f32, z: f32) -> f32 {
+        let cell_size = CHUNK_SIZE / self.erosion.resolution as f32;
+        let band = self.erosion.padding as f32 * cell_size * 0.5;
+        let mut total = 0.0;
+        blend_tiles(x, z, CHUNK_SIZE, band, |(chunk_x, chunk_z), weight| {
+            let tile = self.cache.get_or_insert_with((chunk_x, chunk_z), || {
+                erode_tile(self.base.as_ref(), &self.erosion, chunk_x, chunk_z)
+            });
+            total += weight * tile.delta_at(x, z);
+        });
+        total
+    }
+}
+
+impl HeightSampler for ErodedHeightSampler {
+    fn config(&self) -> &WorldGenConfig {
+        self.base.config()
+    }
+
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.base.height(x, z) + self.delta(x, z)
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Climate {
+        self.base.climate(x, z)
+    }
+
+    fn water_level(&self, x: f32, z: f32) -> f32 {
+        self.base.water_level(x, z)
+    }
+}
+
+/// Run hydraulic then thermal erosion over the padded tile for one chunk, returning the height change
+fn erode_tile(base: &dyn HeightSampler, erosion: &ErosionConfig, chunk_x: i32, chunk_z: i32) -> ErodedTile {
+    let cell_size = CHUNK_SIZE / erosion.resolution as f32;
+    let size = erosion.resolution + 1 + erosion.padding * 2;
+    let origin_x = chunk_x as f32 * CHUNK_SIZE - erosion.padding as f32 * cell_size;
+    let origin_z = chunk_z as f32 * CHUNK_SIZE - erosion.padding as f32 * cell_size;
+
+    let mut heights = Vec::with_capacity(size * size);
+    for j in 0..size {
+        for i in 0..size {
+            heights.push(base.height(origin_x + i as f32 * cell_size, origin_z + j as f32 * cell_size));
+        }
+    }
+    let original = heights.clone();
+
+    // Seeded from the chunk so a tile always erodes the same way
+    let seed = ((chunk_x as u64) << 32) ^ (chunk_z as u32 as u64) ^ base.config().terrain_seed as u64;
+    let mut rng = StdRng::seed_from_u64(seed);
+    hydraulic_erosion(&mut heights, size, cell_size, erosion, &mut rng);
+    thermal_erosion(&mut heights, size, cell_size, erosion);
+
+    let delta = heights.iter().zip(original.iter()).map(|(h, o)| h - o).collect();
+    ErodedTile { origin_x, origin_z, cell_size, size, delta }
+}
+
+/// Height and gradient (per cell) at a fractional grid position
+fn height_and_gradient(heights: &[f32], size: usize, x: f32, z: f32) -> (f32, Vec2) {
+    let ix = x as usize;
+    let iz = z as usize;
+    let fx = x - ix as f32;
+    let fz = z - iz as f32;
+    let h00 = heights[iz * size + ix];
+    let h10 = heights[iz * size + ix + 1];
+    let h01 = heights[(iz + 1) * size + ix];
+    let h11 = heights[(iz + 1) * size + ix + 1];
+    let gradient = Vec2::new(
+        (h10 - h00) * (1.0 - fz) + (h11 - h01) * fz,
+        (h01 - h00) * (1.0 - fx) + (h11 - h10) * fx,
+    );
+    let height = h00 * (1.0 - fx) * (1.0 - fz) + h10 * fx * (1.0 - fz) + h01 * (1.0 - fx) * fz + h11 * fx * fz;
+    (height, gradient)
+}
+
+/// Spread a height change over the four nodes around a fractional grid position
+fn apply_bilinear(heights: &mut [f32], size: usize, x: f32, z: f32, amount: f32) {
+    let ix = x as usize;
+    let iz = z as usize;
+    let fx = x - ix as f32;
+    let fz = z - iz as f32;
+    heights[iz * size + ix] += amount * (1.0 - fx) * (1.0 - fz);
+    heights[iz * size + ix + 1] += amount * fx * (1.0 - fz);
+    heights[(iz + 1) * size + ix] += amount * (1.0 - fx) * fz;
+    heights[(iz + 1) * size + ix + 1] += amount * fx * fz;
+}
+
+/// Droplet based hydraulic erosion: each droplet runs downhill picking up sediment
+/// and drops it again where it slows down or the ground levels out
+fn hydraulic_erosion(heights: &mut [f32], size: usize, cell_size: f32, erosion: &ErosionConfig, rng: &mut StdRng) {
+    let max = (size - 1) as f32;
+    for _ in 0..erosion.droplets {
+        let mut pos = Vec2::new(rng.gen_range(0.0..max), rng.gen_range(0.0..max));
+        let mut dir = Vec2::ZERO;
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..erosion.droplet_lifetime {
+            let (height, gradient) = height_and_gradient(heights, size, pos.x, pos.y);
+            dir = dir * erosion.inertia - gradient * (1.0 - erosion.inertia);
+            if dir.length_squared() < f32::EPSILON {
+                break;
+            }
+            dir = dir.normalize();
+            let old_pos = pos;
+            pos += dir;
+            if pos.x < 0.0 || pos.y < 0.0 || pos.x >= max || pos.y >= max {
+                break;
+            }
+
+            let (new_height, _) = height_and_gradient(heights, size, pos.x, pos.y);
+            let delta_height = new_height - height;
+            // Carrying capacity grows with the drop per cell, speed and remaining water
+            let capacity = (-delta_height / cell_size * speed * water * erosion.sediment_capacity)
+                .max(erosion.min_sediment_capacity);
+
+            if sediment > capacity || delta_height > 0.0 {
+                // Fill the pit we just climbed out of, or drop what we can't carry
+                let deposit = if delta_height > 0.0 {
+                    delta_height.min(sediment)
+                } else {
+                    (sediment - capacity) * erosion.deposit_speed
+                };
+                sediment -= deposit;
+                apply_bilinear(heights, size, old_pos.x, old_pos.y, deposit);
+            } else {
+                // Never dig deeper than the drop, or the droplet would carve a hole it can't leave
+                let erode = ((capacity - sediment) * erosion.erode_speed).min(-delta_height);
+                sediment += erode;
+                apply_bilinear(heights, size, old_pos.x, old_pos.y, -erode);
+            }
+
+            speed = (speed * speed - delta_height / cell_size * erosion.gravity).max(0.0).sqrt();
+            water *= 1.0 - erosion.evaporate_speed;
+        }
+    }
+}
+
+/// Thermal erosion: material slides off any slope steeper than the talus angle onto its lower neighbours
+fn thermal_erosion(heights: &mut [f32], size: usize, cell_size: f32, erosion: &ErosionConfig) {
+    let talus = erosion.talus_angle.tan() * cell_size;
+    let mut changes = vec![0.0; heights.len()];
+    for _ in 0..erosion.thermal_iterations {
+        changes.iter_mut().for_each(|c| *c = 0.0);
+        for j in 1..size - 1 {
+            for i in 1..size - 1 {
+                let index = j * size + i;
+                let h = heights[index];
+                for neighbour in [index - 1, index + 1, index - size, index + size] {
+                    let excess = h - heights[neighbour] - talus;
+                    if excess > 0.0 {
+                        // Quarter each way so a cell can't give away more than it has above its neighbours
+                        let moved = excess * erosion.thermal_rate * 0.25;
+                        changes[index] -= moved;
+                        changes[neighbour] += moved;
+                    }
+                }
+            }
+        }
+        for (h, c) in heights.iter_mut().zip(changes.iter()) {
+            *h += c;
+        }
+    }
+}
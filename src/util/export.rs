@@ -0,0 +1,234 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use crate::entities::terrain::{chunk_to_world, generate_terrain_mesh, NeighbourLods, CHUNK_SIZE, LOD_0_SUBDIVISIONS, TERRAIN_MAX_HEIGHT, TERRAIN_MIN_HEIGHT};
+use crate::util::cli::{ExportArgs, ExportFormat};
+use crate::util::heightfield::{HeightSampler, TerrainSampler};
+use crate::util::worldgen::WorldGenConfig;
+
+/// Height range stored in exported PNGs: all the terrain can reach, so ocean floors and peaks survive,
+/// and fixed so exports of different worlds can be compared.
+/// Import with the same range (see heightmap::HeightmapConfig) to get the heights back.
+pub const PNG_MIN_HEIGHT: f32 = TERRAIN_MIN_HEIGHT;
+pub const PNG_MAX_HEIGHT: f32 = TERRAIN_MAX_HEIGHT;
+
+/// Chunk vertices and triangles in world space
+struct ChunkGeometry {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+/// Write the terrain for a region of chunks to disk, without starting the game
+pub fn run_export(config: &WorldGenConfig, args: &ExportArgs) -> Result<(), String> {
+    let sampler = TerrainSampler::from_config(config);
+    let extension = match args.format {
+        ExportFormat::Png16 => "png",
+        ExportFormat::Obj => "obj",
+        ExportFormat::Gltf => "gltf",
+    };
+    let output = args.output.clone().unwrap_or_else(|| format!("terrain.{}", extension));
+    match args.format {
+        ExportFormat::Png16 => export_png16(&sampler, args.region, &output)?,
+        ExportFormat::Obj => export_obj(&chunk_geometry(config, &sampler, args.region), &output)?,
+        ExportFormat::Gltf => export_gltf(&chunk_geometry(config, &sampler, args.region), &output)?,
+    }
+    println!("Exported chunks {:?} (seed {}) to {}", args.region, config.terrain_seed, output);
+    Ok(())
+}
+
+/// Highest detail mesh of every chunk in the region, moved into world space
+fn chunk_geometry(config: &WorldGenConfig, sampler: &dyn HeightSampler, region: (i32, i32, i32, i32)) -> Vec<ChunkGeometry> {
+    let (x0, z0, x1, z1) = region;
+    // Every chunk is at full detail, so there's nothing to stitch
+    let edge_lods = NeighbourLods {
+        neg_x: LOD_0_SUBDIVISIONS,
+        pos_x: LOD_0_SUBDIVISIONS,
+        neg_z: LOD_0_SUBDIVISIONS,
+        pos_z: LOD_0_SUBDIVISIONS,
+    };
+    let mut chunks = Vec::new();
+    for chunk_z in z0..=z1 {
+        for chunk_x in x0..=x1 {
+            let (world_x, world_z) = chunk_to_world(chunk_x, chunk_z);
+            let mesh = generate_terrain_mesh(config, sampler, world_x, world_z, CHUNK_SIZE, LOD_0_SUBDIVISIONS, edge_lods);
+            let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+                panic!("Unexpected vertex format, expected Float32x3");
+            };
+            let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else {
+                panic!("Unexpected vertex format, expected Float32x3");
+            };
+            let Some(Indices::U32(indices)) = mesh.indices() else {
+                panic!("Unexpected index format, expected U32");
+            };
+            chunks.push(ChunkGeometry {
+                positions: positions.iter().map(|[x, y, z]| [x + world_x, *y, z + world_z]).collect(),
+                normals: normals.clone(),
+                indices: indices.clone(),
+            });
+        }
+    }
+    chunks
+}
+
+/// 16-bit greyscale heightmap at the highest mesh resolution, row 0 along the region's -z edge
+fn export_png16(sampler: &dyn HeightSampler, region: (i32, i32, i32, i32), output: &str) -> Result<(), String> {
+    let (x0, z0, x1, z1) = region;
+    let spacing = CHUNK_SIZE / LOD_0_SUBDIVISIONS as f32;
+    let width = (x1 - x0 + 1) as usize * LOD_0_SUBDIVISIONS as usize + 1;
+    let depth = (z1 - z0 + 1) as usize * LOD_0_SUBDIVISIONS as usize + 1;
+    let origin_x = x0 as f32 * CHUNK_SIZE;
+    let origin_z = z0 as f32 * CHUNK_SIZE;
+
+    let mut data = Vec::with_capacity(width * depth * 2);
+    for row in 0..depth {
+        for col in 0..width {
+            let height = sampler.height(origin_x + col as f32 * spacing, origin_z + row as f32 * spacing);
+            let value = ((height - PNG_MIN_HEIGHT) / (PNG_MAX_HEIGHT - PNG_MIN_HEIGHT)).clamp(0.0, 1.0);
+            data.extend_from_slice(&((value * u16::MAX as f32).round() as u16).to_be_bytes());
+        }
+    }
+
+    let file = File::create(output).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, depth as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&data).map_err(|e| e.to_string())
+}
+
+/// Wavefront OBJ with one group per chunk
+fn export_obj(chunks: &[ChunkGeometry], output: &str) -> Result<(), String> {
+    let file = File::create(output).map_err(|e| e.to_string())?;
+    let mut out = BufWriter::new(file);
+    let mut write = || -> std::io::Result<()> {
+        let mut base = 1;
+        for (i, chunk) in chunks.iter().enumerate() {
+            writeln!(out, "g chunk_{}", i)?;
+            for [x, y, z] in &chunk.positions {
+                writeln!(out, "v {} {} {}", x, y, z)?;
+            }
+            for [x, y, z] in &chunk.normals {
+                writeln!(out, "vn {} {} {}", x, y, z)?;
+            }
+            for tri in chunk.indices.chunks_exact(3) {
+                let (a, b, c) = (tri[0] + base, tri[1] + base, tri[2] + base);
+                writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?;
+            }
+            base += chunk.positions.len() as u32;
+        }
+        out.flush()
+    };
+    write().map_err(|e| e.to_string())
+}
+
+/// glTF 2.0 with one mesh per chunk, written as a .gltf file next to a .bin buffer
+fn export_gltf(chunks: &[ChunkGeometry], output: &str) -> Result<(), String> {
+    let bin_path = Path::new(output).with_extension("bin");
+    let bin_name = bin_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut meshes = Vec::new();
+    let mut nodes = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let min = chunk.positions.iter().fold(Vec3::splat(f32::MAX), |m, p| m.min(Vec3::from(*p)));
+        let max = chunk.positions.iter().fold(Vec3::splat(f32::MIN), |m, p| m.max(Vec3::from(*p)));
+
+        let accessor = accessors.len();
+        let views = [
+            (bytemuck::cast_slice::<[f32; 3], u8>(&chunk.positions), 34962),
+            (bytemuck::cast_slice::<[f32; 3], u8>(&chunk.normals), 34962),
+            (bytemuck::cast_slice::<u32, u8>(&chunk.indices), 34963),
+        ];
+        for (bytes, target) in views {
+            buffer_views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                buffer.len(), bytes.len(), target
+            ));
+            buffer.extend_from_slice(bytes);
+        }
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            accessor, chunk.positions.len(), min.x, min.y, min.z, max.x, max.y, max.z
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3"}}"#,
+            accessor + 1, chunk.normals.len()
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            accessor + 2, chunk.indices.len()
+        ));
+        meshes.push(format!(
+            r#"{{"name":"chunk_{}","primitives":[{{"attributes":{{"POSITION":{},"NORMAL":{}}},"indices":{}}}]}}"#,
+            i, accessor, accessor + 1, accessor + 2
+        ));
+        nodes.push(format!(r#"{{"name":"chunk_{}","mesh":{}}}"#, i, i));
+    }
+
+    let node_indices: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+    let json = format!(
+        r#"{{"asset":{{"version":"2.0","generator":"first-game export"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],"meshes":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"uri":"{}","byteLength":{}}}]}}"#,
+        node_indices.join(","), nodes.join(","), meshes.join(","), accessors.join(","), buffer_views.join(","), bin_name, buffer.len()
+    );
+    fs::write(&bin_path, &buffer).map_err(|e| e.to_string())?;
+    fs::write(output, json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::util::heightfield::PerlinHeightSampler;
+    use crate::util::heightmap::{HeightmapConfig, HeightmapSampler, HeightmapSource};
+
+    #[test]
+    fn png16_export_imports_back_as_a_heightmap() {
+        let config = WorldGenConfig { terrain_seed: 1234, ..default() };
+        let sampler = TerrainSampler::from_config(&config);
+        let directory = std::env::temp_dir().join(format!("export_png16_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        // Two by two chunks reaching from the sea floor up onto land, written where heightmap tile (-8, -3)
+        // of the same size is looked for
+        let (tile_x, tile_z) = (-8, -3);
+        let output = directory.join(format!("height_{}_{}.png", tile_x, tile_z));
+        let region = (tile_x * 2, tile_z * 2, tile_x * 2 + 1, tile_z * 2 + 1);
+        export_png16(&sampler, region, &output.to_string_lossy()).unwrap();
+
+        let heightmaps = HeightmapConfig {
+            directory: directory.to_string_lossy().to_string(),
+            tile_size: 2.0 * CHUNK_SIZE,
+            min_height: PNG_MIN_HEIGHT,
+            max_height: PNG_MAX_HEIGHT,
+            blend_distance: 0.0,
+            meshes: Vec::new(),
+        };
+        let imported = HeightmapSampler::new(Arc::new(PerlinHeightSampler::new(&config)), HeightmapSource::load(&heightmaps));
+        fs::remove_dir_all(&directory).unwrap();
+
+        // One 16-bit step, plus float error
+        let tolerance = (PNG_MAX_HEIGHT - PNG_MIN_HEIGHT) / u16::MAX as f32 + 0.01;
+        let spacing = CHUNK_SIZE / LOD_0_SUBDIVISIONS as f32;
+        let (origin_x, origin_z) = (region.0 as f32 * CHUNK_SIZE, region.1 as f32 * CHUNK_SIZE);
+        let (mut lowest, mut highest) = (f32::MAX, f32::MIN);
+        // The tile's edges fade back to the procedural terrain, so only compare the inside
+        for row in 1..2 * LOD_0_SUBDIVISIONS {
+            for col in 1..2 * LOD_0_SUBDIVISIONS {
+                let (x, z) = (origin_x + col as f32 * spacing, origin_z + row as f32 * spacing);
+                let expected = sampler.height(x, z);
+                let actual = imported.height(x, z);
+                assert!(
+                    (expected - actual).abs() <= tolerance,
+                    "height at ({}, {}) came back as {}, expected {}", x, z, actual, expected,
+                );
+                lowest = lowest.min(expected);
+                highest = highest.max(expected);
+            }
+        }
+        assert!(lowest < 0.0 && highest > 0.0, "the region should span sea level, it's {}..{}", lowest, highest);
+    }
+}
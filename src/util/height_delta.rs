@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::util::biome::Climate;
+use crate::util::heightfield::{HeightSampler, TerrainSampler};
+use crate::util::streaming::TileKey;
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
+
+// Height edits are stored as a grid of points DELTA_SPACING apart, in chunks of
+// DELTA_CHUNK_POINTS x DELTA_CHUNK_POINTS points that only exist once something in them is edited
+const DELTA_SPACING: f32 = 1.0;
+const DELTA_CHUNK_POINTS: i32 = 32;
+// Seconds between announcing the edits of a brush that's held down
+const EDIT_INTERVAL: f32 = 0.25;
+
+/// Sparse offsets added to the generated terrain height, bilinearly interpolated between grid points
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HeightDeltas {
+    chunks: HashMap<TileKey, Vec<f32>>,
+}
+
+impl HeightDeltas {
+    fn locate(ix: i32, iz: i32) -> (TileKey, usize) {
+        let key = (ix.div_euclid(DELTA_CHUNK_POINTS), iz.div_euclid(DELTA_CHUNK_POINTS));
+        let index = iz.rem_euclid(DELTA_CHUNK_POINTS) * DELTA_CHUNK_POINTS + ix.rem_euclid(DELTA_CHUNK_POINTS);
+        (key, index as usize)
+    }
+
+    fn point(&self, ix: i32, iz: i32) -> f32 {
+        let (key, index) = Self::locate(ix, iz);
+        self.chunks.get(&key).map_or(0.0, |chunk| chunk[index])
+    }
+
+    fn add_point(&mut self, ix: i32, iz: i32, delta: f32) {
+        let (key, index) = Self::locate(ix, iz);
+        self.chunks.entry(key).or_insert_with(|| vec![0.0; (DELTA_CHUNK_POINTS * DELTA_CHUNK_POINTS) as usize])[index] += delta;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// The area whose heights, normals or slopes the deltas change, if there are any
+    pub fn bounds(&self) -> Option<Rect> {
+        let chunk_size = DELTA_CHUNK_POINTS as f32 * DELTA_SPACING;
+        // Normals and slopes just outside the edited points change too
+        let margin = DELTA_SPACING * 2.0;
+        self.chunks.keys()
+            .map(|key| {
+                let min = Vec2::new(key.0 as f32, key.1 as f32) * chunk_size;
+                Rect::from_corners(min - margin, min + chunk_size + margin)
+            })
+            .reduce(|a, b| a.union(b))
+    }
+
+    /// Offset at world position (x, z)
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        if self.chunks.is_empty() {
+            return 0.0;
+        }
+        let (gx, gz) = (x / DELTA_SPACING, z / DELTA_SPACING);
+        let (ix, iz) = (gx.floor() as i32, gz.floor() as i32);
+        let (fx, fz) = (gx - ix as f32, gz - iz as f32);
+        let top = self.point(ix, iz) * (1.0 - fx) + self.point(ix + 1, iz) * fx;
+        let bottom = self.point(ix, iz + 1) * (1.0 - fx) + self.point(ix + 1, iz + 1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+}
+
+/// A snapshot of the world's height edits on top of any other sampler layer. Edits made after it was taken
+/// need a new one, see TerrainSampler::with_deltas.
+pub struct HeightDeltaSampler {
+    base: Arc<dyn HeightSampler>,
+    heights: Arc<HeightDeltas>,
+}
+
+impl HeightDeltaSampler {
+    pub fn new(base: Arc<dyn HeightSampler>, heights: Arc<HeightDeltas>) -> Self {
+        Self { base, heights }
+    }
+}
+
+impl HeightSampler for HeightDeltaSampler {
+    fn config(&self) -> &WorldGenConfig {
+        self.base.config()
+    }
+
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.base.height(x, z) + self.heights.sample(x, z)
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Climate {
+        self.base.climate(x, z)
+    }
+
+    fn water_level(&self, x: f32, z: f32) -> f32 {
+        self.base.water_level(x, z)
+    }
+}
+
+/// What a brush does to the ground under it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Brush {
+    Raise,
+    Lower,
+    /// Pull the ground towards this height, e.g. for a building pad
+    Flatten(f32),
+    /// Pull the ground towards the average of its neighbours
+    Smooth,
+}
+
+/// One application of a brush. Its effect fades from full at `center` to nothing at `radius`.
+/// Send it as an event to edit the world.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct DeformTerrain {
+    pub brush: Brush,
+    pub center: Vec2,
+    pub radius: f32,
+    /// World units moved at the center for Raise and Lower, fraction of the way for Flatten and Smooth
+    pub strength: f32,
+}
+
+/// An area of terrain changed; every layer rebuilds its tiles that overlap it
+#[derive(Event, Clone, Copy, Debug)]
+pub struct TerrainEdited {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+/// Apply a brush to the height deltas under it. The surface it works from is `generated` plus the deltas,
+/// so a stroke sees the ones before it even while TerrainSampler still has an older snapshot.
+fn apply_brush(generated: &dyn HeightSampler, heights: &mut HeightDeltas, stroke: &DeformTerrain) {
+    let surface = |x: f32, z: f32| generated.height(x, z) + heights.sample(x, z);
+    let min = ((stroke.center - stroke.radius) / DELTA_SPACING).floor();
+    let max = ((stroke.center + stroke.radius) / DELTA_SPACING).ceil();
+    // Work out every change from the surface before the stroke, then write them, so Smooth doesn't
+    // read points it has already moved
+    let mut changes = Vec::new();
+    for iz in min.y as i32..=max.y as i32 {
+        for ix in min.x as i32..=max.x as i32 {
+            let (x, z) = (ix as f32 * DELTA_SPACING, iz as f32 * DELTA_SPACING);
+            let distance = Vec2::new(x, z).distance(stroke.center) / stroke.radius;
+            if distance >= 1.0 {
+                continue;
+            }
+            let falloff = (1.0 - distance * distance).powi(2);
+            let change = match stroke.brush {
+                Brush::Raise => stroke.strength * falloff,
+                Brush::Lower => -stroke.strength * falloff,
+                Brush::Flatten(target) => (target - surface(x, z)) * (stroke.strength * falloff).min(1.0),
+                Brush::Smooth => {
+                    let d = DELTA_SPACING;
+                    let average = (surface(x - d, z) + surface(x + d, z) + surface(x, z - d) + surface(x, z + d)) / 4.0;
+                    (average - surface(x, z)) * (stroke.strength * falloff).min(1.0)
+                }
+            };
+            changes.push((ix, iz, change));
+        }
+    }
+    for (ix, iz, change) in changes {
+        heights.add_point(ix, iz, change);
+    }
+}
+
+/// Strokes applied to the deltas but not yet announced: the area they cover and how long they've built up for
+#[derive(Default)]
+struct PendingEdit {
+    area: Option<Rect>,
+    age: f32,
+}
+
+/// Apply every stroke as it comes, but only swap in a new TerrainSampler and send TerrainEdited every
+/// EDIT_INTERVAL while a brush is held, and once more when it stops. Each TerrainEdited restarts the
+/// tiles under it, so announcing every frame would keep cancelling them before they finish.
+fn deform_terrain(
+    mut strokes: EventReader<DeformTerrain>,
+    mut edited: EventWriter<TerrainEdited>,
+    mut sampler: ResMut<TerrainSampler>,
+    deltas: Res<WorldDeltas>,
+    mut pending: Local<PendingEdit>,
+    time: Res<Time>,
+) {
+    let mut stroked = false;
+    for stroke in strokes.read() {
+        apply_brush(sampler.generated(), &mut deltas.write().heights, stroke);
+        // Normals and slopes just outside the brush change too
+        let margin = stroke.radius + DELTA_SPACING * 2.0;
+        let area = Rect::from_center_half_size(stroke.center, Vec2::splat(margin));
+        pending.area = Some(pending.area.map_or(area, |pending| pending.union(area)));
+        stroked = true;
+    }
+    let Some(area) = pending.area else { return };
+    pending.age += time.delta_seconds();
+    if stroked && pending.age < EDIT_INTERVAL {
+        return;
+    }
+    *sampler = sampler.with_deltas(&deltas);
+    edited.send(TerrainEdited { min: area.min, max: area.max });
+    *pending = PendingEdit::default();
+}
+
+pub struct HeightDeltaPlugin;
+
+impl Plugin for HeightDeltaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DeformTerrain>()
+            .add_event::<TerrainEdited>()
+            .add_systems(Update, deform_terrain);
+    }
+}
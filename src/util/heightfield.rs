@@ -0,0 +1,377 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use bevy::prelude::*;
+use noise::Perlin;
+use crate::util::biome::{Biome, Climate, ClimateSampler};
+use crate::util::erosion::ErodedHeightSampler;
+use crate::util::height_delta::HeightDeltaSampler;
+use crate::util::heightmap::{HeightmapSampler, HeightmapSource};
+use crate::util::hydrology::HydrologySampler;
+use crate::util::perlin::{self, sample_terrain_height};
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
+
+/// Distance between samples when estimating normals by central differences
+const NORMAL_SAMPLE_DISTANCE: f32 = 1.0;
+
+/// Engine independent view of the terrain surface.
+/// Everything that needs to know the shape of the world (meshes, colliders, grass, trees)
+/// goes through this, so it can be sampled on any thread without a running App.
+pub trait HeightSampler: Send + Sync {
+    /// The config this sampler was built from
+    fn config(&self) -> &WorldGenConfig;
+
+    /// Terrain height at world position (x, z)
+    fn height(&self, x: f32, z: f32) -> f32;
+
+    /// Temperature and moisture at world position (x, z)
+    fn climate(&self, x: f32, z: f32) -> Climate;
+
+    /// Height of the water surface at world position (x, z).
+    /// Sea level everywhere unless a layer adds inland water; water only shows where this is above the terrain.
+    fn water_level(&self, _x: f32, _z: f32) -> f32 {
+        self.config().water_level
+    }
+
+    /// Biome at world position (x, z)
+    fn biome(&self, x: f32, z: f32) -> Biome {
+        Biome::classify(self.config(), self.climate(x, z), self.height(x, z), self.slope(x, z))
+    }
+
+    /// Surface normal at world position (x, z), from central differences of height
+    fn normal(&self, x: f32, z: f32) -> Vec3 {
+        let d = NORMAL_SAMPLE_DISTANCE;
+        let dx = (self.height(x + d, z) - self.height(x - d, z)) / (2.0 * d);
+        let dz = (self.height(x, z + d) - self.height(x, z - d)) / (2.0 * d);
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    /// Angle between the surface normal and straight up, in radians (0 = flat)
+    fn slope(&self, x: f32, z: f32) -> f32 {
+        self.normal(x, z).y.clamp(-1.0, 1.0).acos()
+    }
+}
+
+/// The procedural Perlin noise stack from util::perlin
+pub struct PerlinHeightSampler {
+    config: WorldGenConfig,
+    perlin: Perlin,
+    climate: ClimateSampler,
+}
+
+impl PerlinHeightSampler {
+    pub fn new(config: &WorldGenConfig) -> Self {
+        Self {
+            config: config.clone(),
+            perlin: perlin::terrain_perlin(config),
+            climate: ClimateSampler::new(config),
+        }
+    }
+}
+
+impl HeightSampler for PerlinHeightSampler {
+    fn config(&self) -> &WorldGenConfig {
+        &self.config
+    }
+
+    fn height(&self, x: f32, z: f32) -> f32 {
+        sample_terrain_height(&self.config, &self.perlin, x, z)
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Climate {
+        self.climate.sample(x, z)
+    }
+}
+
+/// Shared handle to the world's height sampler, cheap to clone into async generation tasks.
+/// Height edits are baked in as a snapshot (see with_deltas) and every edit swaps in a new TerrainSampler,
+/// so a task samples the world as it was when the task started and never waits on a lock.
+#[derive(Resource, Clone)]
+pub struct TerrainSampler {
+    /// The terrain as generated, before any edits
+    generated: Arc<dyn HeightSampler>,
+    /// What's sampled: the generated terrain with the edits on top, if there are any
+    sampler: Arc<dyn HeightSampler>,
+}
+
+impl TerrainSampler {
+    /// The noise stack with any optional post-passes from the config layered on top
+    pub fn from_config(config: &WorldGenConfig) -> Self {
+        let mut sampler: Arc<dyn HeightSampler> = Arc::new(PerlinHeightSampler::new(config));
+        // Authored terrain replaces the noise before anything else runs, so it gets eroded and drained too
+        if let Some(heightmaps) = &config.heightmaps {
+            sampler = Arc::new(HeightmapSampler::new(sampler, HeightmapSource::load(heightmaps)));
+        }
+        let uneroded = sampler.clone();
+        if let Some(erosion) = &config.erosion {
+            sampler = Arc::new(ErodedHeightSampler::new(sampler, erosion.clone()));
+        }
+        // Water is routed over the terrain before erosion, which would otherwise have to erode every chunk
+        // a whole region covers before the first chunk could be built. The rivers are carved into the eroded terrain.
+        if let Some(hydrology) = &config.hydrology {
+            sampler = Arc::new(HydrologySampler::new(sampler, uneroded, hydrology.clone()));
+        }
+        TerrainSampler { generated: sampler.clone(), sampler }
+    }
+
+    /// The generated terrain with the world's current height edits on top, replacing any edits this one had
+    pub fn with_deltas(&self, deltas: &WorldDeltas) -> Self {
+        let heights = &deltas.read().heights;
+        let sampler = if heights.is_empty() {
+            self.generated.clone()
+        } else {
+            Arc::new(HeightDeltaSampler::new(self.generated.clone(), Arc::new(heights.clone())))
+        };
+        TerrainSampler { generated: self.generated.clone(), sampler }
+    }
+
+    /// The terrain as generated, without any edits
+    pub fn generated(&self) -> &dyn HeightSampler {
+        self.generated.as_ref()
+    }
+}
+
+impl std::ops::Deref for TerrainSampler {
+    type Target = dyn HeightSampler;
+
+    fn deref(&self) -> &Self::Target {
+        self.sampler.as_ref()
+    }
+}
+
+// Lets a &TerrainSampler be passed wherever a &dyn HeightSampler is wanted
+impl HeightSampler for TerrainSampler {
+    fn config(&self) -> &WorldGenConfig {
+        self.sampler.config()
+    }
+
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.sampler.height(x, z)
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Climate {
+        self.sampler.climate(x, z)
+    }
+
+    fn water_level(&self, x: f32, z: f32) -> f32 {
+        self.sampler.water_level(x, z)
+    }
+
+    fn biome(&self, x: f32, z: f32) -> Biome {
+        self.sampler.biome(x, z)
+    }
+
+    fn normal(&self, x: f32, z: f32) -> Vec3 {
+        self.sampler.normal(x, z)
+    }
+
+    fn slope(&self, x: f32, z: f32) -> f32 {
+        self.sampler.slope(x, z)
+    }
+}
+
+/// Bounded cache of per-tile results for sampler layers that post-process whole tiles of terrain.
+/// Once full, the oldest tiles are dropped first.
+pub struct TileCache<T> {
+    capacity: usize,
+    inner: Mutex<(HashMap<(i32, i32), Arc<OnceLock<Arc<T>>>>, VecDeque<(i32, i32)>)>,
+}
+
+impl<T> TileCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, inner: Mutex::new((HashMap::new(), VecDeque::new())) }
+    }
+
+    /// Cached tile for `key`, building it with `build` on a miss.
+    /// The build runs outside the cache lock; other tasks asking for the same tile wait for it
+    /// instead of building it again.
+    pub fn get_or_insert_with(&self, key: (i32, i32), build: impl FnOnce() -> T) -> Arc<T> {
+        let cell = {
+            let mut inner = self.inner.lock().unwrap();
+            let (tiles, order) = &mut *inner;
+            if let Some(cell) = tiles.get(&key) {
+                cell.clone()
+            } else {
+                let cell = Arc::new(OnceLock::new());
+                tiles.insert(key, cell.clone());
+                order.push_back(key);
+                while order.len() > self.capacity {
+                    if let Some(old) = order.pop_front() {
+                        tiles.remove(&old);
+                    }
+                }
+                cell
+            }
+        };
+        cell.get_or_init(|| Arc::new(build())).clone()
+    }
+}
+
+/// Visit the tiles of a `tile_size` grid that contribute to the point (x, z), with their weights.
+/// Near a tile border the tiles either side are cross-faded over `band` world units, each
+/// contributing half at the border itself, so layers that process tiles independently still
+/// produce a continuous surface. Weights always sum to one.
+pub fn blend_tiles(x: f32, z: f32, tile_size: f32, band: f32, mut visit: impl FnMut((i32, i32), f32)) {
+    let tile_x = (x / tile_size).floor() as i32;
+    let tile_z = (z / tile_size).floor() as i32;
+    let weights_x = border_weights(x - tile_x as f32 * tile_size, tile_size, band);
+    let weights_z = border_weights(z - tile_z as f32 * tile_size, tile_size, band);
+    for (ox, wx) in weights_x {
+        for (oz, wz) in weights_z {
+            let w = wx * wz;
+            if w > 0.0 {
+                visit((tile_x + ox, tile_z + oz), w);
+            }
+        }
+    }
+}
+
+/// Weights of this tile and the nearest neighbour along one axis
+fn border_weights(local: f32, tile_size: f32, band: f32) -> [(i32, f32); 2] {
+    let (offset, distance) = if local < tile_size * 0.5 { (-1, local) } else { (1, tile_size - local) };
+    let t = (distance / band.max(f32::EPSILON)).clamp(0.0, 1.0);
+    let neighbour = 0.5 * (1.0 - t * t * (3.0 - 2.0 * t));
+    [(0, 1.0 - neighbour), (offset, neighbour)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::BufWriter;
+    use crate::entities::terrain::{CHUNK_SIZE, TERRAIN_MAX_HEIGHT, TERRAIN_MIN_HEIGHT};
+    use crate::util::erosion::ErosionConfig;
+    use crate::util::heightmap::HeightmapConfig;
+    use crate::util::hydrology::HydrologyConfig;
+
+    // How far either side of a chunk border the surface is compared
+    const BORDER_EPSILON: f32 = 0.01;
+    const SEEDS: [u32; 6] = [0, 1, 7, 1234, 40658, u32::MAX];
+
+    /// The plain noise stack for `seed`
+    fn perlin_config(seed: u32) -> WorldGenConfig {
+        WorldGenConfig { terrain_seed: seed, ..default() }
+    }
+
+    /// Every optional layer turned on, kept small enough to run quickly.
+    /// `directory` gets one authored tile, (0, 0), straddling several chunk borders.
+    fn layered_config(seed: u32, directory: &str) -> WorldGenConfig {
+        WorldGenConfig {
+            terrain_seed: seed,
+            heightmaps: Some(HeightmapConfig {
+                directory: directory.to_string(),
+                tile_size: CHUNK_SIZE * 2.0,
+                min_height: 180.0,
+                max_height: 260.0,
+                blend_distance: 64.0,
+                ..default()
+            }),
+            erosion: Some(ErosionConfig { resolution: 32, padding: 8, droplets: 2000, ..default() }),
+            hydrology: Some(HydrologyConfig { region_cells: 64, padding: 16, river_threshold: 50.0, ..default() }),
+            ..default()
+        }
+    }
+
+    /// Write a rolling 16-bit height tile to a fresh directory for this test
+    fn write_height_tile(test: &str) -> String {
+        let directory = std::env::temp_dir().join(format!("heightfield_{}_{}", test, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let size = 33;
+        let file = File::create(directory.join("height_0_0.png")).unwrap();
+        let mut encoder = png::Encoder::new(BufWriter::new(file), size, size);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut data = Vec::with_capacity((size * size * 2) as usize);
+        for row in 0..size {
+            for col in 0..size {
+                let value = 0.5 + 0.5 * (col as f32 * 0.4).sin() * (row as f32 * 0.3).cos();
+                data.extend_from_slice(&((value * u16::MAX as f32) as u16).to_be_bytes());
+            }
+        }
+        encoder.write_header().unwrap().write_image_data(&data).unwrap();
+        directory.to_string_lossy().to_string()
+    }
+
+    /// Points just either side of the chunk borders around the origin, along both axes
+    fn border_pairs() -> Vec<((f32, f32), (f32, f32))> {
+        let mut pairs = Vec::new();
+        for chunk in -2..=3 {
+            let border = chunk as f32 * CHUNK_SIZE;
+            for along in [-CHUNK_SIZE * 0.7, 3.0, CHUNK_SIZE * 0.45, CHUNK_SIZE * 1.3] {
+                pairs.push(((border - BORDER_EPSILON, along), (border + BORDER_EPSILON, along)));
+                pairs.push(((along, border - BORDER_EPSILON), (along, border + BORDER_EPSILON)));
+            }
+        }
+        pairs
+    }
+
+    fn assert_continuous(sampler: &TerrainSampler) {
+        for ((ax, az), (bx, bz)) in border_pairs() {
+            let (a, b) = (sampler.height(ax, az), sampler.height(bx, bz));
+            assert!((a - b).abs() < 0.05, "height jumps from {} to {} across the border at ({}, {})", a, b, bx, bz);
+            let (a, b) = (sampler.normal(ax, az), sampler.normal(bx, bz));
+            assert!(a.dot(b) > 0.999, "normal jumps from {} to {} across the border at ({}, {})", a, b, bx, bz);
+        }
+    }
+
+    fn assert_bounded(sampler: &TerrainSampler, positions: impl Iterator<Item = (f32, f32)>) {
+        for (x, z) in positions {
+            let height = sampler.height(x, z);
+            assert!(
+                (TERRAIN_MIN_HEIGHT..=TERRAIN_MAX_HEIGHT).contains(&height),
+                "height {} at ({}, {}) with seed {} is outside {}..={}",
+                height, x, z, sampler.config().terrain_seed, TERRAIN_MIN_HEIGHT, TERRAIN_MAX_HEIGHT,
+            );
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_heights() {
+        for seed in SEEDS {
+            let (a, b) = (TerrainSampler::from_config(&perlin_config(seed)), TerrainSampler::from_config(&perlin_config(seed)));
+            for i in -50..50 {
+                let (x, z) = (i as f32 * 97.3, i as f32 * -61.9 + 12.5);
+                assert_eq!(a.height(x, z), b.height(x, z));
+                assert_eq!(a.normal(x, z), b.normal(x, z));
+            }
+        }
+        let (a, b) = (TerrainSampler::from_config(&perlin_config(1)), TerrainSampler::from_config(&perlin_config(2)));
+        assert!((0..100).any(|i| a.height(i as f32 * 53.0, 0.0) != b.height(i as f32 * 53.0, 0.0)));
+    }
+
+    #[test]
+    fn perlin_is_continuous_across_chunk_borders() {
+        for seed in SEEDS {
+            assert_continuous(&TerrainSampler::from_config(&perlin_config(seed)));
+        }
+    }
+
+    #[test]
+    fn layers_are_continuous_across_chunk_borders() {
+        let directory = write_height_tile("continuity");
+        for seed in [0, 40658] {
+            assert_continuous(&TerrainSampler::from_config(&layered_config(seed, &directory)));
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn perlin_stays_in_terrain_bounds() {
+        for seed in SEEDS {
+            let sampler = TerrainSampler::from_config(&perlin_config(seed));
+            let steps = -200..200;
+            assert_bounded(&sampler, steps.clone().flat_map(|i| steps.clone().map(move |j| (i as f32 * 331.0, j as f32 * 331.0))));
+        }
+    }
+
+    #[test]
+    fn layers_stay_in_terrain_bounds() {
+        let directory = write_height_tile("bounds");
+        for seed in [0, 40658] {
+            let sampler = TerrainSampler::from_config(&layered_config(seed, &directory));
+            let steps = -8..24;
+            assert_bounded(&sampler, steps.clone().flat_map(|i| steps.clone().map(move |j| (i as f32 * 61.0, j as f32 * 61.0))));
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::Arc;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::util::biome::Climate;
+use crate::util::heightfield::HeightSampler;
+use crate::util::worldgen::WorldGenConfig;
+
+// Grid cells along the longer side of a rasterised glTF mesh
+const MESH_RASTER_RESOLUTION: usize = 256;
+
+/// Hand authored terrain layered over the procedural noise.
+/// Heightmap tiles are 16-bit (or 8-bit) greyscale PNGs named `height_{x}_{z}.png`,
+/// tile (x, z) covering world x in [x * tile_size, (x + 1) * tile_size) and the same for z.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeightmapConfig {
+    /// Directory searched for height tiles
+    pub directory: String,
+    /// World units covered by one tile
+    pub tile_size: f32,
+    /// Heights that black and white pixels map to
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Width of the blend back to procedural terrain at tile edges with no neighbouring tile
+    pub blend_distance: f32,
+    /// glTF meshes rasterised into heights, applied over the tiles
+    pub meshes: Vec<HeightmapMesh>,
+}
+
+impl Default for HeightmapConfig {
+    fn default() -> Self {
+        Self {
+            directory: "assets/terrain/height".to_string(),
+            tile_size: 2048.0,
+            min_height: 150.0,
+            max_height: 900.0,
+            blend_distance: 256.0,
+            meshes: Vec::new(),
+        }
+    }
+}
+
+/// A glTF mesh placed in the world as terrain
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeightmapMesh {
+    pub path: String,
+    /// Where the mesh origin goes in the world
+    pub center_x: f32,
+    pub center_z: f32,
+    /// World units per mesh unit, horizontally and vertically
+    pub scale: f32,
+    pub height_scale: f32,
+    /// Height of the mesh origin
+    pub base_height: f32,
+}
+
+impl Default for HeightmapMesh {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            center_x: 0.0,
+            center_z: 0.0,
+            scale: 512.0,
+            height_scale: 1024.0,
+            base_height: 200.0,
+        }
+    }
+}
+
+/// Regular grid of authored heights. NaN marks points the source doesn't cover.
+struct HeightGrid {
+    origin_x: f32,
+    origin_z: f32,
+    spacing_x: f32,
+    spacing_z: f32,
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+    /// Which edges (-x, +x, -z, +z) fade back to the procedural terrain
+    fade_edges: [bool; 4],
+}
+
+impl HeightGrid {
+    fn extent_x(&self) -> f32 {
+        self.spacing_x * (self.width - 1) as f32
+    }
+
+    fn extent_z(&self) -> f32 {
+        self.spacing_z * (self.depth - 1) as f32
+    }
+
+    /// Bilinear height at (x, z), or None outside the grid or where it isn't covered
+    fn sample(&self, x: f32, z: f32) -> Option<f32> {
+        let gx = (x - self.origin_x) / self.spacing_x;
+        let gz = (z - self.origin_z) / self.spacing_z;
+        if gx < 0.0 || gz < 0.0 || gx > (self.width - 1) as f32 || gz > (self.depth - 1) as f32 {
+            return None;
+        }
+        let ix = (gx as usize).min(self.width - 2);
+        let iz = (gz as usize).min(self.depth - 2);
+        let fx = gx - ix as f32;
+        let fz = gz - iz as f32;
+        let h = |i: usize, j: usize| self.heights[j * self.width + i];
+        let top = h(ix, iz) * (1.0 - fx) + h(ix + 1, iz) * fx;
+        let bottom = h(ix, iz + 1) * (1.0 - fx) + h(ix + 1, iz + 1) * fx;
+        let height = top * (1.0 - fz) + bottom * fz;
+        (!height.is_nan()).then_some(height)
+    }
+
+    /// How much of the authored height to use at (x, z): 1 inside, fading to 0 at fading edges
+    fn weight(&self, x: f32, z: f32, blend_distance: f32) -> f32 {
+        let distances = [
+            x - self.origin_x,
+            self.origin_x + self.extent_x() - x,
+            z - self.origin_z,
+            self.origin_z + self.extent_z() - z,
+        ];
+        let mut weight: f32 = 1.0;
+        for (distance, fade) in distances.iter().zip(self.fade_edges) {
+            if fade {
+                let t = (distance / blend_distance.max(f32::EPSILON)).clamp(0.0, 1.0);
+                weight = weight.min(t * t * (3.0 - 2.0 * t));
+            }
+        }
+        weight
+    }
+}
+
+/// All the authored terrain found on disk for a config
+pub struct HeightmapSource {
+    tiles: HashMap<(i32, i32), HeightGrid>,
+    meshes: Vec<HeightGrid>,
+    tile_size: f32,
+    blend_distance: f32,
+}
+
+impl HeightmapSource {
+    /// Load every height tile in the configured directory and rasterise the configured meshes.
+    /// Files that fail to load are reported and skipped.
+    pub fn load(heightmaps: &HeightmapConfig) -> Self {
+        let mut images = HashMap::new();
+        if let Ok(entries) = fs::read_dir(&heightmaps.directory) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let Some(key) = parse_tile_name(&name) else { continue };
+                match read_png_heights(&entry.path().to_string_lossy()) {
+                    Ok(image) => { images.insert(key, image); }
+                    Err(e) => warn!("Skipping height tile {}: {}", name, e),
+                }
+            }
+        } else {
+            warn!("No heightmap directory found at {}", heightmaps.directory);
+        }
+
+        // Edges only fade where there's no neighbouring tile to continue into
+        let mut tiles = HashMap::new();
+        for (&(tile_x, tile_z), (width, depth, values)) in images.iter() {
+            let range = heightmaps.max_height - heightmaps.min_height;
+            tiles.insert((tile_x, tile_z), HeightGrid {
+                origin_x: tile_x as f32 * heightmaps.tile_size,
+                origin_z: tile_z as f32 * heightmaps.tile_size,
+                spacing_x: heightmaps.tile_size / (*width - 1) as f32,
+                spacing_z: heightmaps.tile_size / (*depth - 1) as f32,
+                width: *width,
+                depth: *depth,
+                heights: values.iter().map(|v| heightmaps.min_height + v * range).collect(),
+                fade_edges: [
+                    !images.contains_key(&(tile_x - 1, tile_z)),
+                    !images.contains_key(&(tile_x + 1, tile_z)),
+                    !images.contains_key(&(tile_x, tile_z - 1)),
+                    !images.contains_key(&(tile_x, tile_z + 1)),
+                ],
+            });
+        }
+
+        let meshes = heightmaps.meshes.iter().filter_map(|mesh| {
+            rasterise_gltf(mesh).map_err(|e| warn!("Skipping heightmap mesh {}: {}", mesh.path, e)).ok()
+        }).collect();
+
+        Self { tiles, meshes, tile_size: heightmaps.tile_size, blend_distance: heightmaps.blend_distance }
+    }
+
+    /// Authored height at (x, z) and how strongly it applies, if any source covers the point.
+    /// Meshes are applied over tiles.
+    fn sample(&self, x: f32, z: f32) -> Option<(f32, f32)> {
+        let key = ((x / self.tile_size).floor() as i32, (z / self.tile_size).floor() as i32);
+        let mut result = self.tiles.get(&key)
+            .and_then(|tile| tile.sample(x, z).map(|h| (h, tile.weight(x, z, self.blend_distance))));
+        for mesh in &self.meshes {
+            if let Some(h) = mesh.sample(x, z) {
+                let w = mesh.weight(x, z, self.blend_distance);
+                result = Some(match result {
+                    Some((under, under_w)) => (under + (h - under) * w, under_w.max(w)),
+                    None => (h, w),
+                });
+            }
+        }
+        result
+    }
+}
+
+/// Tile coordinates from a `height_{x}_{z}.png` file name
+fn parse_tile_name(name: &str) -> Option<(i32, i32)> {
+    let coords = name.strip_prefix("height_")?.strip_suffix(".png")?;
+    let (x, z) = coords.split_once('_')?;
+    Some((x.parse().ok()?, z.parse().ok()?))
+}
+
+/// Decode a greyscale PNG into (width, height, values in 0..1), row 0 being the tile's -z edge.
+/// Colour images use their first channel.
+fn read_png_heights(path: &str) -> Result<(usize, usize, Vec<f32>), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    if width < 2 || height < 2 {
+        return Err("heightmap must be at least 2x2".to_string());
+    }
+
+    let samples = info.color_type.samples();
+    let mut values = Vec::with_capacity(width * height);
+    for row in 0..height {
+        let line = &buf[row * info.line_size..(row + 1) * info.line_size];
+        for col in 0..width {
+            let value = match info.bit_depth {
+                png::BitDepth::Sixteen => {
+                    let i = col * samples * 2;
+                    u16::from_be_bytes([line[i], line[i + 1]]) as f32 / u16::MAX as f32
+                }
+                _ => line[col * samples] as f32 / u8::MAX as f32,
+            };
+            values.push(value);
+        }
+    }
+    Ok((width, height, values))
+}
+
+/// Rasterise every triangle of a glTF scene into a height grid (highest surface wins)
+fn rasterise_gltf(mesh: &HeightmapMesh) -> Result<HeightGrid, String> {
+    let (document, buffers, _) = gltf::import(&mesh.path).map_err(|e| e.to_string())?;
+    let placement = Mat4::from_translation(Vec3::new(mesh.center_x, mesh.base_height, mesh.center_z))
+        * Mat4::from_scale(Vec3::new(mesh.scale, mesh.height_scale, mesh.scale));
+
+    let mut triangles: Vec<[Vec3; 3]> = Vec::new();
+    let mut stack: Vec<(gltf::Node, Mat4)> = document.scenes()
+        .flat_map(|scene| scene.nodes())
+        .map(|node| (node, placement))
+        .collect();
+    while let Some((node, parent)) = stack.pop() {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        if let Some(node_mesh) = node.mesh() {
+            for primitive in node_mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(buffers[buffer.index()].0.as_slice()));
+                let Some(positions) = reader.read_positions() else { continue };
+                let positions: Vec<Vec3> = positions.map(|p| transform.transform_point3(Vec3::from(p))).collect();
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                for tri in indices.chunks_exact(3) {
+                    triangles.push([positions[tri[0] as usize], positions[tri[1] as usize], positions[tri[2] as usize]]);
+                }
+            }
+        }
+        stack.extend(node.children().map(|child| (child, transform)));
+    }
+    if triangles.is_empty() {
+        return Err("no triangles".to_string());
+    }
+
+    let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+    for p in triangles.iter().flatten() {
+        min = min.min(*p);
+        max = max.max(*p);
+    }
+    let spacing = (max.x - min.x).max(max.z - min.z) / MESH_RASTER_RESOLUTION as f32;
+    let width = ((max.x - min.x) / spacing).ceil() as usize + 1;
+    let depth = ((max.z - min.z) / spacing).ceil() as usize + 1;
+    let mut heights = vec![f32::NAN; width * depth];
+
+    for [a, b, c] in triangles {
+        let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        let to_grid = |v: f32, origin: f32| ((v - origin) / spacing).max(0.0);
+        let i0 = to_grid(a.x.min(b.x).min(c.x), min.x).floor() as usize;
+        let i1 = (to_grid(a.x.max(b.x).max(c.x), min.x).ceil() as usize).min(width - 1);
+        let j0 = to_grid(a.z.min(b.z).min(c.z), min.z).floor() as usize;
+        let j1 = (to_grid(a.z.max(b.z).max(c.z), min.z).ceil() as usize).min(depth - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let (x, z) = (min.x + i as f32 * spacing, min.z + j as f32 * spacing);
+                // Barycentric coordinates in the xz plane
+                let wb = ((x - a.x) * (c.z - a.z) - (c.x - a.x) * (z - a.z)) / area;
+                let wc = ((b.x - a.x) * (z - a.z) - (x - a.x) * (b.z - a.z)) / area;
+                let wa = 1.0 - wb - wc;
+                if wa < -1e-4 || wb < -1e-4 || wc < -1e-4 {
+                    continue;
+                }
+                let y = a.y * wa + b.y * wb + c.y * wc;
+                let cell = &mut heights[j * width + i];
+                if cell.is_nan() || y > *cell {
+                    *cell = y;
+                }
+            }
+        }
+    }
+
+    Ok(HeightGrid {
+        origin_x: min.x,
+        origin_z: min.z,
+        spacing_x: spacing,
+        spacing_z: spacing,
+        width,
+        depth,
+        heights,
+        fade_edges: [true; 4],
+    })
+}
+
+/// Wraps another sampler, replacing it with authored heights wherever a heightmap covers the world
+pub struct HeightmapSampler {
+    base: Arc<dyn HeightSampler>,
+    source: HeightmapSource,
+}
+
+impl HeightmapSampler {
+    pub fn new(base: Arc<dyn HeightSampler>, source: HeightmapSource) -> Self {
+        Self { base, source }
+    }
+}
+
+impl HeightSampler for HeightmapSampler {
+    fn config(&self) -> &WorldGenConfig {
+        self.base.config()
+    }
+
+    fn height(&self, x: f32, z: f32) -> f32 {
+        let procedural = self.base.height(x, z);
+        match self.source.sample(x, z) {
+            Some((authored, weight)) => procedural + (authored - procedural) * weight,
+            None => procedural,
+        }
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Climate {
+        self.base.climate(x, z)
+    }
+
+    fn water_level(&self, x: f32, z: f32) -> f32 {
+        self.base.water_level(x, z)
+    }
+}
@@ -0,0 +1,246 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::util::biome::Climate;
+use crate::util::heightfield::{blend_tiles, HeightSampler, TileCache};
+use crate::util::worldgen::WorldGenConfig;
+
+// Regions kept around before the oldest are dropped (each is a few MB)
+const MAX_CACHED_REGIONS: usize = 64;
+// Height added per cell when filling depressions, so filled lakes still drain towards their outlet
+const FILL_EPSILON: f32 = 0.001;
+// Fraction of a river bed that is filled with water
+const RIVER_FILL: f32 = 0.75;
+// How far below the ground the water surface sits where there's no water,
+// so interpolating towards a dry cell slopes the surface under the bank
+const DRY_OFFSET: f32 = 4.0;
+
+/// Settings for rivers and lakes.
+/// Flow is routed over one padded region at a time, so catchments larger than the padding
+/// are only partly seen and their rivers start further downstream than they would globally.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HydrologyConfig {
+    /// World units between hydrology grid cells
+    pub cell_size: f32,
+    /// Cells along one side of a region
+    pub region_cells: usize,
+    /// Extra cells routed around each region
+    pub padding: usize,
+    /// Upstream cells draining through a cell before it becomes a river
+    pub river_threshold: f32,
+    /// Depth of the bed carved under a river at the threshold, grows with flow
+    pub river_depth: f32,
+    pub max_river_depth: f32,
+    /// Depressions shallower than this are left dry rather than filled into lakes
+    pub min_lake_depth: f32,
+}
+
+impl Default for HydrologyConfig {
+    fn default() -> Self {
+        Self {
+            cell_size: 16.0,
+            region_cells: 256,
+            padding: 64,
+            river_threshold: 400.0,
+            river_depth: 3.0,
+            max_river_depth: 12.0,
+            min_lake_depth: 0.5,
+        }
+    }
+}
+
+impl HydrologyConfig {
+    /// Reject settings the routing can't run with
+    pub fn validate(&self) -> Result<(), String> {
+        if self.region_cells == 0 {
+            return Err("hydrology region_cells must be at least 1".to_string());
+        }
+        if self.cell_size <= 0.0 {
+            return Err("hydrology cell_size must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// River carving and water surface over one padded region
+struct WaterTile {
+    origin_x: f32,
+    origin_z: f32,
+    cell_size: f32,
+    size: usize,
+    /// Depth carved out of the terrain (rivers)
+    carve: Vec<f32>,
+    /// Water surface height (lakes and rivers), below the ground where it's dry
+    surface: Vec<f32>,
+}
+
+impl WaterTile {
+    /// Bilinear sample of `values` at a world position covered by this tile
+    fn sample(&self, values: &[f32], x: f32, z: f32) -> f32 {
+        let gx = ((x - self.origin_x) / self.cell_size).clamp(0.0, (self.size - 1) as f32);
+        let gz = ((z - self.origin_z) / self.cell_size).clamp(0.0, (self.size - 1) as f32);
+        let ix = (gx as usize).min(self.size - 2);
+        let iz = (gz as usize).min(self.size - 2);
+        let fx = gx - ix as f32;
+        let fz = gz - iz as f32;
+        let v = |i: usize, j: usize| values[j * self.size + i];
+        let top = v(ix, iz) * (1.0 - fx) + v(ix + 1, iz) * fx;
+        let bottom = v(ix, iz + 1) * (1.0 - fx) + v(ix + 1, iz + 1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+}
+
+/// Wraps another sampler, carving river beds into it and adding lakes and rivers above sea level.
+/// Water is routed over `routed`, a cheaper version of the same terrain (e.g. before erosion).
+pub struct HydrologySampler {
+    base: Arc<dyn HeightSampler>,
+    routed: Arc<dyn HeightSampler>,
+    hydrology: HydrologyConfig,
+    cache: TileCache<WaterTile>,
+}
+
+impl HydrologySampler {
+    pub fn new(base: Arc<dyn HeightSampler>, routed: Arc<dyn HeightSampler>, hydrology: HydrologyConfig) -> Self {
+        Self { base, routed, hydrology, cache: TileCache::new(MAX_CACHED_REGIONS) }
+    }
+
+    fn region_size(&self) -> f32 {
+        self.hydrology.region_cells as f32 * self.hydrology.cell_size
+    }
+
+    /// Weighted sum of a tile field at (x, z), cross-faded between regions over half the padding
+    fn blended(&self, x: f32, z: f32, field: impl Fn(&WaterTile) -> &[f32]) -> f32 {
+        let band = self.hydrology.padding as f32 * self.hydrology.cell_size * 0.5;
+        let mut total = 0.0;
+        blend_tiles(x, z, self.region_size(), band, |(region_x, region_z), weight| {
+            let tile = self.cache.get_or_insert_with((region_x, region_z), || {
+                route_region(self.routed.as_ref(), &self.hydrology, region_x, region_z)
+            });
+            total += weight * tile.sample(field(tile.as_ref()), x, z);
+        });
+        total
+    }
+}
+
+impl HeightSampler for HydrologySampler {
+    fn config(&self) -> &WorldGenConfig {
+        self.base.config()
+    }
+
+    fn height(&self, x: f32, z: f32) -> f32 {
+        self.base.height(x, z) - self.blended(x, z, |tile| tile.carve.as_slice())
+    }
+
+    fn climate(&self, x: f32, z: f32) -> Climate {
+        self.base.climate(x, z)
+    }
+
+    fn water_level(&self, x: f32, z: f32) -> f32 {
+        self.base.water_level(x, z).max(self.blended(x, z, |tile| tile.surface.as_slice()))
+    }
+}
+
+/// Cell in the priority flood queue, lowest filled height first
+#[derive(PartialEq)]
+struct FloodCell {
+    height: f32,
+    index: usize,
+}
+
+impl Eq for FloodCell {}
+
+impl Ord for FloodCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.height.total_cmp(&self.height).then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Route water over one padded region.
+/// A priority flood from the region border and the sea fills every depression up to its spill
+/// height (those become lakes) and gives each cell the neighbour it drains into. Summing flow
+/// down that drainage tree marks the rivers, which get a bed carved in proportion to their flow.
+fn route_region(base: &dyn HeightSampler, hydrology: &HydrologyConfig, region_x: i32, region_z: i32) -> WaterTile {
+    let cell_size = hydrology.cell_size;
+    let size = hydrology.region_cells + 1 + hydrology.padding * 2;
+    let region_size = hydrology.region_cells as f32 * cell_size;
+    let origin_x = region_x as f32 * region_size - hydrology.padding as f32 * cell_size;
+    let origin_z = region_z as f32 * region_size - hydrology.padding as f32 * cell_size;
+    let sea_level = base.config().water_level;
+
+    let mut heights = Vec::with_capacity(size * size);
+    for j in 0..size {
+        for i in 0..size {
+            heights.push(base.height(origin_x + i as f32 * cell_size, origin_z + j as f32 * cell_size));
+        }
+    }
+
+    // Priority flood, seeded from everything that can drain freely: the border and the sea
+    let cell_count = size * size;
+    let mut filled = heights.clone();
+    let mut receiver = vec![usize::MAX; cell_count];
+    let mut visited = vec![false; cell_count];
+    let mut order = Vec::with_capacity(cell_count);
+    let mut queue = BinaryHeap::new();
+    for index in 0..cell_count {
+        let (i, j) = (index % size, index / size);
+        if i == 0 || j == 0 || i == size - 1 || j == size - 1 || heights[index] <= sea_level {
+            visited[index] = true;
+            queue.push(FloodCell { height: heights[index], index });
+        }
+    }
+    while let Some(FloodCell { index, .. }) = queue.pop() {
+        order.push(index);
+        let (i, j) = ((index % size) as i32, (index / size) as i32);
+        for (di, dj) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (ni, nj) = (i + di, j + dj);
+            if ni < 0 || nj < 0 || ni >= size as i32 || nj >= size as i32 {
+                continue;
+            }
+            let neighbour = nj as usize * size + ni as usize;
+            if visited[neighbour] {
+                continue;
+            }
+            visited[neighbour] = true;
+            filled[neighbour] = heights[neighbour].max(filled[index] + FILL_EPSILON);
+            receiver[neighbour] = index;
+            queue.push(FloodCell { height: filled[neighbour], index: neighbour });
+        }
+    }
+
+    // Flow accumulation, from the highest cells down
+    let mut flow = vec![1.0f32; cell_count];
+    for &index in order.iter().rev() {
+        if receiver[index] != usize::MAX {
+            flow[receiver[index]] += flow[index];
+        }
+    }
+
+    let mut carve = vec![0.0; cell_count];
+    let mut surface = vec![0.0; cell_count];
+    for index in 0..cell_count {
+        let height = heights[index];
+        surface[index] = height - DRY_OFFSET;
+        if height <= sea_level {
+            // The ocean covers this
+            continue;
+        }
+        if filled[index] - height > hydrology.min_lake_depth {
+            surface[index] = filled[index];
+        } else if flow[index] >= hydrology.river_threshold {
+            let depth = (hydrology.river_depth * (1.0 + (flow[index] / hydrology.river_threshold).ln()))
+                .min(hydrology.max_river_depth);
+            carve[index] = depth;
+            surface[index] = height - depth * (1.0 - RIVER_FILL);
+        }
+    }
+
+    WaterTile { origin_x, origin_z, cell_size, size, carve, surface }
+}
@@ -1,10 +1,17 @@
-use std::f32::consts::PI;
+use std::f32::consts::{PI, TAU};
 
 use bevy::{pbr::{light_consts::lux::AMBIENT_DAYLIGHT, CascadeShadowConfigBuilder, DirectionalLightShadowMap}, prelude::*};
 use bevy_atmosphere::{collection::nishita::Nishita, model::AtmosphereModel, system_param::AtmosphereMut};
 
 // TODO: blue moonlit sky at night
 
+// Seconds for the sun to move one radian, so a full day lasts 64 * TAU seconds
+const DAY_SPEED: f32 = 64.0;
+
+/// Angle of the sun in radians: 0 at sunrise, PI / 2 at noon, PI at sunset. Saved with the game.
+#[derive(Resource, Clone, Copy, Default, Debug)]
+pub struct TimeOfDay(pub f32);
+
 pub fn setup_lighting(mut commands: Commands) {
     commands.spawn( setup_sun() );
     commands.spawn( setup_moon() );
@@ -70,12 +77,14 @@ fn daylight_cycle(
     mut moon: Query<(&mut Transform, &mut DirectionalLight), (With<Moon>,Without<Sun>)>,
     mut ambient: ResMut<AmbientLight>,
     mut timer: ResMut<CycleTimer>,
+    mut time_of_day: ResMut<TimeOfDay>,
     time: Res<Time>,
 ) {
     timer.0.tick(time.delta());
+    time_of_day.0 = (time_of_day.0 + time.delta_seconds() / DAY_SPEED) % TAU;
 
     if timer.0.finished() {
-        let t = time.elapsed_seconds_wrapped() / 64.0;
+        let t = time_of_day.0;
         atmosphere.sun_position = Vec3::new(0., t.sin(), t.cos());
 
         if let Some((mut light_trans, mut directional)) = sun.single_mut().into() {
@@ -119,7 +128,8 @@ impl Plugin for LightingPlugin {
             )))
             .insert_resource(DirectionalLightShadowMap {
                 size: 4096
-            });
+            })
+            .init_resource::<TimeOfDay>();
         app.add_systems(Startup, setup_lighting);
         app.add_systems(Update, daylight_cycle);
     }
@@ -1,6 +1,19 @@
 pub mod lighting;
+pub mod biome;
 pub mod camera;
+pub mod cli;
+pub mod erosion;
+pub mod export;
 pub mod gravity;
+pub mod height_delta;
+pub mod heightfield;
+pub mod heightmap;
+pub mod hydrology;
 pub mod perlin;
 pub mod render_state;
+pub mod save;
+pub mod streaming;
+pub mod wind;
+pub mod world_delta;
+pub mod worldgen;
 // pub mod audio;
\ No newline at end of file
@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::Path;
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use crate::entities::player::Player;
+use crate::util::height_delta::TerrainEdited;
+use crate::util::heightfield::TerrainSampler;
+use crate::util::lighting::TimeOfDay;
+use crate::util::world_delta::{WorldDelta, WorldDeltas};
+use crate::util::worldgen::WorldGenConfig;
+
+/// Version written into new saves. Bump it when SaveGame changes shape and teach `migrate` the old one.
+pub const SAVE_VERSION: u32 = 1;
+pub const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+const SAVE_KEY: KeyCode = KeyCode::F5;
+const LOAD_KEY: KeyCode = KeyCode::F9;
+
+/// Everything needed to pick a game back up: the world it was played in, where the player was,
+/// and every edit made to the world since it was generated
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub world: WorldGenConfig,
+    pub player: PlayerSave,
+    /// TimeOfDay
+    pub time_of_day: f32,
+    pub delta: WorldDelta,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSave {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+/// Just enough of any version of a save to know how to read the rest
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl SaveGame {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let header: SaveHeader = ron::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+        migrate(header.version, &contents).map_err(|e| format!("Failed to load {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        if let Some(directory) = Path::new(path).parent() {
+            fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+        }
+        // Height deltas are long lists of numbers, keep them on one line each
+        let pretty = PrettyConfig::new().depth_limit(4);
+        let contents = ron::ser::to_string_pretty(self, pretty).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e))
+    }
+}
+
+/// Read a save written as `version`, upgrading it to the current SaveGame.
+/// Each old version gets an arm that parses its own layout (kept as e.g. SaveGameV1) and converts it.
+fn migrate(version: u32, contents: &str) -> Result<SaveGame, String> {
+    match version {
+        SAVE_VERSION => ron::from_str(contents).map_err(|e| e.to_string()),
+        _ if version > SAVE_VERSION => Err(format!("save version {} is newer than this game ({})", version, SAVE_VERSION)),
+        _ => Err(format!("save version {} is no longer supported", version)),
+    }
+}
+
+/// A save to restore once the world is set up, from --load or the load key
+#[derive(Resource)]
+pub struct PendingLoad(pub SaveGame);
+
+fn save_game(
+    keys: Res<ButtonInput<KeyCode>>,
+    config: Res<WorldGenConfig>,
+    player: Query<&Transform, With<Player>>,
+    time_of_day: Res<TimeOfDay>,
+    deltas: Res<WorldDeltas>,
+) {
+    if !keys.just_pressed(SAVE_KEY) {
+        return;
+    }
+    let Ok(plyr_trans) = player.get_single() else { return };
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        world: config.clone(),
+        player: PlayerSave { translation: plyr_trans.translation.to_array(), rotation: plyr_trans.rotation.to_array() },
+        time_of_day: time_of_day.0,
+        delta: deltas.read().clone(),
+    };
+    match save.save(QUICKSAVE_PATH) {
+        Ok(()) => info!("Saved to {}", QUICKSAVE_PATH),
+        Err(e) => error!("Save failed: {}", e),
+    }
+}
+
+fn load_game(mut commands: Commands, keys: Res<ButtonInput<KeyCode>>, config: Res<WorldGenConfig>) {
+    if !keys.just_pressed(LOAD_KEY) {
+        return;
+    }
+    match SaveGame::load(QUICKSAVE_PATH) {
+        // The sampler and every layer are built from the config, so another world needs a fresh start
+        Ok(save) if save.world != *config => {
+            warn!("{} is from a different world, start the game with --load {} to play it", QUICKSAVE_PATH, QUICKSAVE_PATH);
+        }
+        Ok(save) => commands.insert_resource(PendingLoad(save)),
+        Err(e) => error!("{}", e),
+    }
+}
+
+/// Put the player, the time of day and the world's edits back the way the save has them
+fn apply_pending_load(
+    mut commands: Commands,
+    pending: Option<Res<PendingLoad>>,
+    mut player: Query<&mut Transform, With<Player>>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    deltas: Res<WorldDeltas>,
+    mut sampler: ResMut<TerrainSampler>,
+    mut edited: EventWriter<TerrainEdited>,
+) {
+    let Some(pending) = pending else { return };
+    let Ok(mut plyr_trans) = player.get_single_mut() else { return };
+    let save = &pending.0;
+    plyr_trans.translation = Vec3::from_array(save.player.translation);
+    plyr_trans.rotation = Quat::from_array(save.player.rotation);
+    time_of_day.0 = save.time_of_day;
+    // Layers share the deltas, so swapping what's inside is enough; the sampler takes a new snapshot of the heights
+    let previous = deltas.read().bounds();
+    *deltas.write() = save.delta.clone();
+    *sampler = sampler.with_deltas(&deltas);
+    // Only tiles the old or the new edits touch need rebuilding, and they go through the streaming budget
+    if let Some(area) = previous.into_iter().chain(save.delta.bounds()).reduce(|a, b| a.union(b)) {
+        edited.send(TerrainEdited { min: area.min, max: area.max });
+    }
+    commands.remove_resource::<PendingLoad>();
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (save_game, load_game, apply_pending_load).chain());
+    }
+}
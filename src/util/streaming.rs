@@ -0,0 +1,354 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use bevy::utils::{HashMap, HashSet};
+use futures_lite::future::poll_once;
+use crate::entities::player::Player;
+use crate::util::height_delta::TerrainEdited;
+use crate::util::heightfield::TerrainSampler;
+use crate::util::render_state::RenderState;
+use crate::util::world_delta::WorldDeltas;
+use crate::util::worldgen::WorldGenConfig;
+
+/// Integer tile coordinates; tile (x, z) covers [x * tile_size, (x + 1) * tile_size) on each axis
+pub type TileKey = (i32, i32);
+
+/// How a layer's tiles are laid out and streamed around the player
+#[derive(Clone, Copy, Debug)]
+pub struct StreamingSettings {
+    /// World units along one side of a tile
+    pub tile_size: f32,
+    /// Tiles kept loaded in each direction from the player's tile
+    pub radius: i32,
+    /// Tile tasks started per frame, for new tiles and regenerated ones alike (urgent tiles don't count against it)
+    pub max_spawns_per_frame: usize,
+}
+
+impl StreamingSettings {
+    /// Tile containing a world position
+    pub fn world_to_tile(&self, x: f32, z: f32) -> TileKey {
+        ((x / self.tile_size).floor() as i32, (z / self.tile_size).floor() as i32)
+    }
+
+    /// World position of a tile's center
+    pub fn tile_center(&self, key: TileKey) -> (f32, f32) {
+        ((key.0 as f32 + 0.5) * self.tile_size, (key.1 as f32 + 0.5) * self.tile_size)
+    }
+
+    /// Tiles that should be loaded with the player in `player_key`, with their distance from it
+    pub fn wanted_tiles(&self, player_key: TileKey) -> impl Iterator<Item = (TileKey, i32)> {
+        let radius = self.radius;
+        (-radius..=radius).flat_map(move |dx| {
+            (-radius..=radius).map(move |dz| ((player_key.0 + dx, player_key.1 + dz), dx.abs().max(dz.abs())))
+        })
+    }
+
+    /// Whether a loaded tile stays loaded. There's one tile of slack past the radius so tiles don't flicker at the edge.
+    pub fn keeps(&self, key: TileKey, player_key: TileKey) -> bool {
+        tile_distance(key, player_key) <= self.radius + 1
+    }
+}
+
+/// Seed for anything randomised within a tile, the same every time the tile is generated
+pub fn tile_seed(world_seed: u32, key: TileKey) -> u64 {
+    let hash = (key.0 as i64).wrapping_mul(73856093) ^ (key.1 as i64).wrapping_mul(19349663);
+    (hash as u64) ^ ((world_seed as u64) << 32)
+}
+
+/// Chebyshev distance between two tiles
+pub fn tile_distance(a: TileKey, b: TileKey) -> i32 {
+    (a.0 - b.0).abs().max((a.1 - b.1).abs())
+}
+
+/// How much more a tile behind the player waits than one straight ahead.
+/// 1.0 means a tile behind is started as if it were twice as far away.
+const VIEW_DIRECTION_WEIGHT: f32 = 1.0;
+
+/// Lower is more urgent: tile distance, stretched for tiles away from where the player is facing
+fn tile_priority(settings: &StreamingSettings, key: TileKey, distance: i32, player_pos: Vec2, forward: Vec2) -> f32 {
+    let (x, z) = settings.tile_center(key);
+    let facing = (Vec2::new(x, z) - player_pos).normalize_or_zero().dot(forward);
+    distance as f32 * (1.0 + VIEW_DIRECTION_WEIGHT * (1.0 - facing) / 2.0)
+}
+
+/// A tile waiting for a generation task, either missing or loaded but out of date
+struct QueuedTile {
+    key: TileKey,
+    distance: i32,
+    priority: f32,
+}
+
+// Ordered so a BinaryHeap pops the lowest priority value first
+impl Ord for QueuedTile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
+impl PartialOrd for QueuedTile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedTile {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedTile {}
+
+/// What a generation task is being asked to build
+#[derive(Clone, Copy, Debug)]
+pub struct TileRequest<Lod> {
+    pub key: TileKey,
+    pub lod: Lod,
+    /// Distance in tiles from the player when the task was started
+    pub distance: i32,
+    /// False when regenerating a tile that's already in the world at a new LOD
+    pub first: bool,
+    /// True when regenerating a tile because the ground under it was edited
+    pub edited: bool,
+    /// Matches StreamedTile::generation while this is the tile's latest task
+    pub generation: u32,
+}
+
+/// One streamed layer of the world (terrain, grass, trees...).
+/// The grid decides which tiles exist and at what LOD; the layer only builds and places them.
+pub trait LayerGenerator: Send + Sync + Sized + 'static {
+    /// Level of detail. A tile whose LOD changes is regenerated.
+    type Lod: Copy + PartialEq + Send + Sync + 'static;
+    /// Result of a background generation task
+    type Output: Send + 'static;
+
+    fn settings() -> StreamingSettings;
+
+    /// Build the generator once the world gen resources exist.
+    /// Layers that show world edits keep the deltas and read them while generating.
+    fn new(config: &WorldGenConfig, sampler: &TerrainSampler, deltas: &WorldDeltas) -> Self;
+
+    /// LOD for a tile, given the tile the player is in
+    fn lod(key: TileKey, player_key: TileKey) -> Self::Lod;
+
+    /// Tiles that skip the per-frame budget, e.g. terrain the player is about to stand on
+    fn urgent(_distance: i32) -> bool {
+        false
+    }
+
+    /// Build a tile. Runs on the async compute pool, so it mustn't touch the World.
+    fn generate(&self, request: &TileRequest<Self::Lod>) -> Self::Output;
+
+    /// Put a finished tile into the world on its entity (the entity already exists)
+    fn apply(world: &mut World, entity: Entity, request: TileRequest<Self::Lod>, output: Self::Output);
+}
+
+/// A tile the grid knows about
+pub struct StreamedTile<Lod> {
+    pub entity: Entity,
+    pub state: RenderState,
+    pub lod: Lod,
+    /// Bumped every time a task is started for the tile, so older results can be told apart
+    pub generation: u32,
+}
+
+/// Every tile of one layer around the player
+#[derive(Resource)]
+pub struct StreamingGrid<L: LayerGenerator> {
+    generator: Arc<L>,
+    tiles: HashMap<TileKey, StreamedTile<L::Lod>>,
+    next_generation: u32,
+    /// Tiles whose contents changed since they were generated (see regenerate)
+    stale: HashSet<TileKey>,
+}
+
+impl<L: LayerGenerator> StreamingGrid<L> {
+    pub fn get(&self, key: TileKey) -> Option<&StreamedTile<L::Lod>> {
+        self.tiles.get(&key)
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = (&TileKey, &StreamedTile<L::Lod>)> {
+        self.tiles.iter()
+    }
+
+    /// Rebuild every loaded tile overlapping the rectangle from `min` to `max` on the xz plane
+    pub fn regenerate_area(&mut self, min: Vec2, max: Vec2) {
+        let settings = L::settings();
+        let (min_key, max_key) = (settings.world_to_tile(min.x, min.y), settings.world_to_tile(max.x, max.y));
+        let overlapping = self.tiles.keys()
+            .filter(|(x, z)| (min_key.0..=max_key.0).contains(x) && (min_key.1..=max_key.1).contains(z));
+        self.stale.extend(overlapping);
+    }
+
+    /// Rebuild a tile at its current LOD, e.g. after a world edit. It stays in the world until the new version is ready.
+    /// Does nothing if the tile isn't loaded, it'll pick up the edit when it's generated.
+    pub fn regenerate(&mut self, key: TileKey) {
+        if self.tiles.contains_key(&key) {
+            self.stale.insert(key);
+        }
+    }
+
+    fn next_generation(&mut self) -> u32 {
+        self.next_generation = self.next_generation.wrapping_add(1);
+        self.next_generation
+    }
+}
+
+/// Background generation for one tile, on the tile's entity.
+/// Inserting a new task or despawning the entity drops the old task, which cancels it.
+#[derive(Component)]
+struct GenTileTask<L: LayerGenerator> {
+    task: Task<L::Output>,
+    request: TileRequest<L::Lod>,
+}
+
+fn start_task<L: LayerGenerator>(commands: &mut Commands, generator: &Arc<L>, entity: Entity, request: TileRequest<L::Lod>) {
+    let generator = generator.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move { generator.generate(&request) });
+    commands.entity(entity).insert(GenTileTask::<L> { task, request });
+}
+
+/// Despawn tiles that are out of range, then start tasks for new tiles and for tiles whose LOD changed or
+/// that were edited, within the per-frame budget
+fn stream_tiles<L: LayerGenerator>(
+    mut commands: Commands,
+    grid: Option<ResMut<StreamingGrid<L>>>,
+    config: Res<WorldGenConfig>,
+    sampler: Res<TerrainSampler>,
+    deltas: Res<WorldDeltas>,
+    player: Query<&Transform, With<Player>>,
+) {
+    let Ok(player_trans) = player.get_single() else { return };
+    let Some(mut grid) = grid else {
+        commands.insert_resource(StreamingGrid::<L> {
+            generator: Arc::new(L::new(&config, &sampler, &deltas)),
+            tiles: HashMap::new(),
+            next_generation: 0,
+            stale: HashSet::new(),
+        });
+        return;
+    };
+    // Edits swap in a new sampler. Tasks started from now on sample it, running ones finish with the one they had.
+    if sampler.is_changed() {
+        grid.generator = Arc::new(L::new(&config, &sampler, &deltas));
+    }
+    let settings = L::settings();
+    let player_key = settings.world_to_tile(player_trans.translation.x, player_trans.translation.z);
+    let player_pos = player_trans.translation.xz();
+    let forward = (player_trans.rotation * -Vec3::Z).xz().normalize_or_zero();
+    let generator = grid.generator.clone();
+
+    // Despawn tiles that are too far. Despawning a pending tile cancels its task.
+    grid.tiles.retain(|key, tile| {
+        let keep = settings.keeps(*key, player_key);
+        if !keep {
+            commands.entity(tile.entity).despawn_recursive();
+        }
+        keep
+    });
+
+    let mut stale = std::mem::take(&mut grid.stale);
+    stale.retain(|key| grid.tiles.contains_key(key));
+
+    // Queue loaded tiles whose LOD changed or that were edited, and missing tiles, closest and most in view first
+    let mut queue = BinaryHeap::new();
+    for (key, tile) in grid.tiles.iter() {
+        if tile.lod != L::lod(*key, player_key) || stale.contains(key) {
+            let distance = tile_distance(*key, player_key);
+            let priority = tile_priority(&settings, *key, distance, player_pos, forward);
+            queue.push(QueuedTile { key: *key, distance, priority });
+        }
+    }
+    for (key, distance) in settings.wanted_tiles(player_key) {
+        if !grid.tiles.contains_key(&key) {
+            let priority = tile_priority(&settings, key, distance, player_pos, forward);
+            queue.push(QueuedTile { key, distance, priority });
+        }
+    }
+
+    let mut started = 0;
+    while let Some(QueuedTile { key, distance, .. }) = queue.pop() {
+        let urgent = L::urgent(distance);
+        if !urgent && started >= settings.max_spawns_per_frame {
+            continue;
+        }
+        let lod = L::lod(key, player_key);
+        let generation = grid.next_generation();
+        if let Some(tile) = grid.tiles.get_mut(&key) {
+            // Out of date: the tile stays in the world until the new version is ready.
+            // The new task replaces (and cancels) any task still running for the tile.
+            let edited = stale.remove(&key);
+            tile.lod = lod;
+            tile.generation = generation;
+            let request = TileRequest { key, lod, distance, first: tile.state == RenderState::Pending, edited, generation };
+            start_task(&mut commands, &generator, tile.entity, request);
+        } else {
+            let entity = commands.spawn_empty().id();
+            start_task(&mut commands, &generator, entity, TileRequest { key, lod, distance, first: true, edited: false, generation });
+            grid.tiles.insert(key, StreamedTile { entity, state: RenderState::Pending, lod, generation });
+        }
+        if !urgent {
+            started += 1;
+        }
+    }
+    // Edited tiles that didn't fit in this frame's budget wait for the next one
+    grid.stale.extend(stale);
+}
+
+/// Rebuild tiles on ground that's been dug, raised or flattened
+fn regenerate_edited_tiles<L: LayerGenerator>(mut edits: EventReader<TerrainEdited>, grid: Option<ResMut<StreamingGrid<L>>>) {
+    let Some(mut grid) = grid else { return };
+    for edit in edits.read() {
+        grid.regenerate_area(edit.min, edit.max);
+    }
+}
+
+/// Hand finished tiles to their layer and mark them visible.
+/// Results for tiles that have since left range or been given a newer task are thrown away.
+fn finish_tile_tasks<L: LayerGenerator>(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut GenTileTask<L>)>,
+    grid: Option<ResMut<StreamingGrid<L>>>,
+) {
+    let Some(mut grid) = grid else { return };
+    for (entity, mut task) in &mut tasks {
+        let Some(output) = block_on(poll_once(&mut task.task)) else { continue };
+        let request = task.request;
+        commands.entity(entity).remove::<GenTileTask<L>>();
+        match grid.tiles.get_mut(&request.key) {
+            Some(tile) if tile.entity == entity && tile.generation == request.generation => {
+                tile.state = RenderState::Visible;
+            }
+            Some(tile) if tile.entity == entity => continue, // superseded, the newer task will finish later
+            _ => {
+                // The key left range (and maybe came back as a new entity); don't leave an orphan behind
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+        }
+        commands.add(move |world: &mut World| {
+            // The tile may have been despawned since the task finished
+            if world.get_entity(entity).is_some() {
+                L::apply(world, entity, request, output);
+            }
+        });
+    }
+}
+
+/// Streams one layer's tiles around the player
+pub struct StreamingPlugin<L>(PhantomData<fn() -> L>);
+
+impl<L> Default for StreamingPlugin<L> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<L: LayerGenerator> Plugin for StreamingPlugin<L> {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (regenerate_edited_tiles::<L>, stream_tiles::<L>, finish_tile_tasks::<L>).chain());
+    }
+}
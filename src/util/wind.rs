@@ -0,0 +1,128 @@
+use std::f32::consts::FRAC_PI_4;
+use bevy::prelude::*;
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy::render::render_resource::ShaderType;
+use noise::{NoiseFn, Perlin};
+use crate::util::worldgen::WorldGenConfig;
+
+// How the wind changes over time
+const GUST_FREQUENCY: f64 = 0.15; // gusts come and go every several seconds
+const VEER_FREQUENCY: f64 = 0.02; // the direction wanders much slower
+const VEER_ANGLE: f32 = 0.6; // radians either side of the prevailing direction
+const SCROLL_SPEED: f32 = 0.5; // how fast turbulence drifts downwind, in noise cells per second at speed 1
+// Foliage leans downwind on average and turbulence rocks it either side.
+// Keep in sync with SWAY_BIAS in wind.wgsl
+const SWAY_BIAS: f32 = 0.3;
+
+/// The wind everywhere in the world. Shaders get it as a WindUniform, CPU systems read it directly
+/// so anything that moves with the wind (foliage, flags, particles, ambient audio) agrees on the gusts.
+#[derive(Resource, Clone, Debug)]
+pub struct Wind {
+    /// Unit vector on the xz plane the wind blows towards
+    pub direction: Vec2,
+    /// Speed between gusts; 1.0 sways a blade's tip about its own height
+    pub strength: f32,
+    /// How much a full gust adds on top of the strength (0 is a steady wind)
+    pub gustiness: f32,
+    /// World units across one patch of turbulence
+    pub turbulence_scale: f32,
+    /// Current gust, 0 (lull) to 1 (full gust)
+    pub gust: f32,
+    /// Offset of the turbulence in noise space, drifting downwind so gusts travel across the world
+    pub scroll: Vec2,
+    /// Angle of the direction the wind veers around
+    prevailing: f32,
+    noise: Perlin,
+}
+
+impl Wind {
+    pub fn new(config: &WorldGenConfig) -> Self {
+        Self {
+            direction: Vec2::from_angle(FRAC_PI_4),
+            strength: 1.0,
+            gustiness: 0.5,
+            turbulence_scale: 50.0,
+            gust: 0.0,
+            scroll: Vec2::ZERO,
+            prevailing: FRAC_PI_4,
+            noise: Perlin::new(config.wind_seed),
+        }
+    }
+
+    /// Current speed, gusts included
+    pub fn speed(&self) -> f32 {
+        self.strength * (1.0 + self.gustiness * self.gust)
+    }
+
+    /// Turbulence at a world position, about -1..1
+    pub fn turbulence(&self, x: f32, z: f32) -> f32 {
+        let p = Vec2::new(x, z) / self.turbulence_scale + self.scroll;
+        self.noise.get([p.x as f64, p.y as f64]) as f32
+    }
+
+    /// How far the top of something one unit tall is pushed, like wind_sway in the shaders
+    pub fn sway(&self, x: f32, z: f32) -> Vec2 {
+        self.direction * self.speed() * (SWAY_BIAS + self.turbulence(x, z))
+    }
+
+    /// Quick noise over time for things that flutter (flames, flags), about -1..1, calmer between gusts
+    pub fn flutter(&self, t: f64) -> f32 {
+        self.noise.get([t, 20.5]) as f32 * (0.5 + self.gust / 2.0)
+    }
+
+    pub fn uniform(&self) -> WindUniform {
+        WindUniform {
+            direction: self.direction,
+            speed: self.speed(),
+            turbulence_scale: self.turbulence_scale,
+            scroll: self.scroll,
+        }
+    }
+}
+
+/// Wind as the shaders see it, see wind.wgsl
+#[derive(Resource, ShaderType, Clone, Copy, Default, Debug)]
+pub struct WindUniform {
+    pub direction: Vec2,
+    pub speed: f32,
+    pub turbulence_scale: f32,
+    pub scroll: Vec2,
+}
+
+impl ExtractResource for WindUniform {
+    type Source = Wind;
+
+    fn extract_resource(wind: &Wind) -> Self {
+        wind.uniform()
+    }
+}
+
+/// Keeps wind.wgsl loaded so the shaders importing it resolve
+#[derive(Resource)]
+struct WindShader(#[allow(dead_code)] Handle<Shader>);
+
+fn setup_wind(mut commands: Commands, config: Res<WorldGenConfig>, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Wind::new(&config));
+    commands.insert_resource(WindShader(asset_server.load("shaders/wind.wgsl")));
+}
+
+/// Gusts rise and fall, the direction veers around the prevailing one, and turbulence drifts downwind
+pub fn update_wind(mut wind: ResMut<Wind>, time: Res<Time>) {
+    let t = time.elapsed_seconds_f64();
+    let gust = wind.noise.get([t * GUST_FREQUENCY, 0.5]) as f32;
+    wind.gust = ((gust + 1.0) / 2.0).clamp(0.0, 1.0);
+    let veer = wind.noise.get([t * VEER_FREQUENCY, 10.5]) as f32 * VEER_ANGLE;
+    wind.direction = Vec2::from_angle(wind.prevailing + veer);
+    let drift = wind.direction * wind.speed() * SCROLL_SPEED * time.delta_seconds();
+    wind.scroll -= drift;
+}
+
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractResourcePlugin::<WindUniform>::default())
+            .add_systems(PreStartup, setup_wind)
+            .add_systems(Update, update_wind);
+    }
+}
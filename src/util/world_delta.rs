@@ -0,0 +1,62 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::entities::tree::{TreeId, TreeLayer};
+use crate::util::height_delta::HeightDeltas;
+use crate::util::streaming::LayerGenerator;
+
+/// Everything that's been changed in the generated world. Generation always starts from the seed,
+/// then applies these, so edits survive their tiles streaming out and back in.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldDelta {
+    /// Trees that have been felled
+    pub removed_trees: HashSet<TreeId>,
+    /// Damage taken by trees still standing
+    pub tree_damage: HashMap<TreeId, f32>,
+    /// Dug, raised and flattened ground, see HeightDeltaSampler
+    pub heights: HeightDeltas,
+}
+
+impl WorldDelta {
+    pub fn is_tree_removed(&self, id: TreeId) -> bool {
+        self.removed_trees.contains(&id)
+    }
+
+    /// The area of the world that looks different for these edits, if there are any
+    pub fn bounds(&self) -> Option<Rect> {
+        let settings = TreeLayer::settings();
+        let felled = self.removed_trees.iter().map(|id| {
+            let (x, z) = settings.tile_center(id.tile);
+            Rect::from_center_half_size(Vec2::new(x, z), Vec2::splat(settings.tile_size / 2.0))
+        });
+        self.heights.bounds().into_iter().chain(felled).reduce(|a, b| a.union(b))
+    }
+}
+
+/// Shared handle to the world's edits, cheap to clone into async generation tasks like TerrainSampler
+#[derive(Resource, Clone, Default)]
+pub struct WorldDeltas(Arc<RwLock<WorldDelta>>);
+
+impl WorldDeltas {
+    pub fn new(delta: WorldDelta) -> Self {
+        Self(Arc::new(RwLock::new(delta)))
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, WorldDelta> {
+        self.0.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, WorldDelta> {
+        self.0.write().unwrap()
+    }
+}
+
+pub struct WorldDeltaPlugin;
+
+impl Plugin for WorldDeltaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldDeltas>();
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::entities::tree_species::TreeSpeciesConfig;
+use crate::util::cli::CliArgs;
+use crate::util::erosion::ErosionConfig;
+use crate::util::heightmap::HeightmapConfig;
+use crate::util::hydrology::HydrologyConfig;
+
+pub const DEFAULT_CONFIG_PATH: &str = "assets/config/worldgen.ron";
+
+/// All the knobs that define a generated world.
+/// Loaded once at startup from a RON file so worlds can be explored and reproduced without recompiling.
+/// Any field missing from the file falls back to its default below.
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldGenConfig {
+    // Seeds
+    pub terrain_seed: u32,
+    pub grass_height_seed: u32,
+    pub wind_seed: u32,
+    pub temperature_seed: u32,
+    pub moisture_seed: u32,
+
+    // Noise stack amplitudes (see util::perlin)
+    pub hill_heights: f32,
+    pub terrain_bumpiness: f32,
+    pub mountain_heights: f32,
+
+    // Terrain levels and height bands
+    pub base_level: f32,
+    pub water_level: f32,
+    pub height_sand: f32,
+    pub height_temperate_start: f32,
+    pub height_temperate_end: f32,
+    pub height_peaks: f32,
+
+    // Biomes: world units per climate noise period
+    pub climate_scale: f32,
+    // Tree shapes, see entities::tree_species
+    pub trees: TreeSpeciesConfig,
+
+    // Hand authored heightmap tiles and meshes, off when missing
+    pub heightmaps: Option<HeightmapConfig>,
+    // Optional erosion post-pass, off when missing
+    pub erosion: Option<ErosionConfig>,
+    // Rivers and lakes above sea level, off when missing
+    pub hydrology: Option<HydrologyConfig>,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        Self {
+            terrain_seed: 40658,
+            grass_height_seed: 1,
+            wind_seed: 0,
+            temperature_seed: 2,
+            moisture_seed: 3,
+            hill_heights: 10.0,
+            terrain_bumpiness: 2.0,
+            mountain_heights: 256.,
+            base_level: 200.,
+            water_level: 189.,
+            height_sand: 200.,
+            height_temperate_start: 210.,
+            height_temperate_end: 800.,
+            height_peaks: 1500.,
+            climate_scale: 2048.,
+            trees: TreeSpeciesConfig::default(),
+            heightmaps: None,
+            erosion: None,
+            hydrology: None,
+        }
+    }
+}
+
+impl WorldGenConfig {
+    /// Read a config from a RON file, falling back to defaults if the file doesn't exist.
+    pub fn load(path: &str) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let config: Self = ron::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+                config.validate().map_err(|e| format!("Invalid {}: {}", path, e))?;
+                Ok(config)
+            }
+            Err(_) => {
+                warn!("No world gen config found at {}, using defaults", path);
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// Check the optional passes can run with the settings given
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(erosion) = &self.erosion {
+            erosion.validate()?;
+        }
+        if let Some(hydrology) = &self.hydrology {
+            hydrology.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Resolve the config for this run: the config file (or --config path), then CLI overrides on top.
+    pub fn from_cli(args: &CliArgs) -> Result<Self, String> {
+        let path = args.config_path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+        let mut config = Self::load(path)?;
+        if let Some(seed) = args.seed {
+            config.terrain_seed = seed;
+        }
+        Ok(config)
+    }
+}